@@ -0,0 +1,2 @@
+# Credentials are taken from MINIO_ROOT_USER and MINIO_ROOT_PASSWORD, see `s3_round_trip` in src/store.rs
+minio server ./minio --address "${MINIO_ADDRESS:-127.0.0.1:9000}"
@@ -1,6 +1,6 @@
 use crate::database::Database;
 use crate::error::Error;
-use crate::{config, user};
+use crate::{bot, config, moderation, user};
 use argon2::password_hash::phc::Salt;
 use argon2::{Argon2, Params, PasswordHasher, PasswordVerifier, Version};
 use elysium_rust::common::v1::ErrorCode;
@@ -68,6 +68,14 @@ pub async fn verify<T>(database: &Database, req: &Request<T>) -> Result<User, Er
 
     if let Some(token) = meta.get("Authorization") {
         let token = String::from_utf8_lossy(token.as_bytes());
+
+        if let Some(token) = token.strip_prefix("Bot ") {
+            let user = bot::verify(database, token).await?;
+            moderation::check_banned(database, &user.user_id).await?;
+
+            return Ok(user);
+        }
+
         let claim =
             jsonwebtoken::decode::<Auth>(token.as_bytes(), key, &Validation::new(Algorithm::EdDSA))
                 .map_err(|err| match err.kind() {
@@ -78,9 +86,13 @@ pub async fn verify<T>(database: &Database, req: &Request<T>) -> Result<User, Er
                     _ => Error::new(ErrorCode::Unauthorized, "Invalid token"),
                 })?;
 
-        user::get(database, &claim.claims.user_id)
+        let user = user::get(database, &claim.claims.user_id)
             .await?
-            .ok_or(Error::new(ErrorCode::NotFound, "User not found"))
+            .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?;
+
+        moderation::check_banned(database, &user.user_id).await?;
+
+        Ok(user)
     } else {
         Err(Error::new(ErrorCode::Unauthorized, "Missing token"))
     }
@@ -100,7 +112,14 @@ pub async fn auth(database: &Database, user_id: String, password: String) -> Res
     let (key, _) = keys();
 
     if let Some(user) = user {
-        if verify_hash(password, user.password) {
+        if bot::is_bot(database, &user.user_id).await? {
+            Err(Error::new(
+                ErrorCode::Unauthorized,
+                "Bots cannot log in with a password",
+            ))
+        } else if verify_hash(password, user.password) {
+            moderation::check_banned(database, &user.user_id).await?;
+
             jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &auth, key)
                 .map_err(|_| Error::new(ErrorCode::Internal, "Failed to encode token"))
         } else {
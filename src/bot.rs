@@ -0,0 +1,227 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::{user, utils};
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Content, Timestamp, User};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use surrealdb::types::SurrealValue;
+use tokio::sync::broadcast;
+
+/// Length of generated bot token secrets.
+pub const SECRET_LENGTH: usize = 32;
+
+/// Maximum number of commands buffered per bot before slow connections start lagging.
+pub const COMMAND_BUFFER_SIZE: usize = 64;
+
+/// Time a bot has to reply to a command.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+pub async fn create(database: &Database, bot: Bot) -> Result<Bot, Error> {
+    let bot: Option<Bot> = database
+        .create(("bot", bot.user_id.as_str()))
+        .content(bot)
+        .await?;
+
+    bot.ok_or(Error::new(ErrorCode::Internal, "Failed to create bot"))
+}
+
+pub async fn get(database: &Database, user_id: &str) -> Result<Option<Bot>, Error> {
+    let bot: Option<Bot> = database.select(("bot", user_id)).await?;
+
+    Ok(bot)
+}
+
+pub async fn delete(database: &Database, user_id: &str) -> Result<(), Error> {
+    let _: Option<Bot> = database.delete(("bot", user_id)).await?;
+
+    Ok(())
+}
+
+pub async fn is_bot(database: &Database, user_id: &str) -> Result<bool, Error> {
+    Ok(get(database, user_id).await?.is_some())
+}
+
+/// Resolves a bot token of the form `<user_id>.<secret>` to the bot user.
+pub async fn verify(database: &Database, token: &str) -> Result<User, Error> {
+    let invalid = || Error::new(ErrorCode::Unauthorized, "Invalid token");
+
+    let (user_id, secret) = token.rsplit_once('.').ok_or_else(invalid)?;
+
+    let bot = get(database, user_id).await?.ok_or_else(invalid)?;
+
+    if utils::hash_token(secret) != bot.token_hash {
+        return Err(invalid());
+    }
+
+    user::get(database, &bot.user_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "User not found"))
+}
+
+/// Finds the bot in the channel handling the given command.
+pub async fn find_command_bot(
+    database: &Database,
+    members: impl Iterator<Item = &String>,
+    command: &str,
+) -> Result<Option<Bot>, Error> {
+    let members = members.cloned().collect::<Vec<_>>();
+
+    let bot: Option<Bot> = database
+        .query("SELECT * FROM bot WHERE user_id IN $members AND $command IN commands LIMIT 1;")
+        .bind(("members", members))
+        .bind(("command", command.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(bot)
+}
+
+pub fn build_secret() -> String {
+    nanoid::nanoid!(SECRET_LENGTH)
+}
+
+/// Splits a `/command args` text into the command name and its arguments.
+pub fn parse_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('/')?;
+    let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+
+    if command.is_empty()
+        || !command
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+
+    Some((command, args.trim()))
+}
+
+/// Routes commands to connected bots and keeps track of unanswered commands.
+#[derive(Clone, Debug)]
+pub struct Bots {
+    connections: Arc<RwLock<HashMap<String, broadcast::Sender<Command>>>>,
+    pending: Arc<Mutex<HashMap<String, Command>>>,
+}
+
+impl Bots {
+    pub fn new() -> Self {
+        Self {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn connect(&self, bot_id: &str) -> broadcast::Receiver<Command> {
+        let mut connections = self.connections.write().expect("Bot connections poisoned");
+
+        connections
+            .entry(bot_id.to_string())
+            .or_insert_with(|| broadcast::channel(COMMAND_BUFFER_SIZE).0)
+            .subscribe()
+    }
+
+    /// Checks whether the bot has a command stream open.
+    pub fn is_connected(&self, bot_id: &str) -> bool {
+        self.connections
+            .read()
+            .expect("Bot connections poisoned")
+            .get(bot_id)
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    /// Sends a command to the bot, failing if the bot is not connected.
+    pub fn dispatch(&self, command: Command) -> Result<(), Error> {
+        let mut connections = self.connections.write().expect("Bot connections poisoned");
+
+        let sent = connections
+            .get(&command.bot_id)
+            .is_some_and(|sender| sender.send(command.clone()).is_ok());
+
+        if !sent {
+            connections.remove(&command.bot_id);
+
+            return Err(Error::new(ErrorCode::NotFound, "Bot is not connected"));
+        }
+
+        self.pending
+            .lock()
+            .expect("Pending commands poisoned")
+            .insert(command.command_id.clone(), command);
+
+        Ok(())
+    }
+
+    /// Takes the pending command a bot replies to.
+    pub fn take(&self, bot_id: &str, command_id: &str) -> Option<Command> {
+        let mut pending = self.pending.lock().expect("Pending commands poisoned");
+
+        if pending.get(command_id)?.bot_id != bot_id {
+            return None;
+        }
+
+        pending.remove(command_id)
+    }
+
+    /// Forgets commands the bot did not reply to in time.
+    pub fn sweep(&self) {
+        let deadline = utils::get_timestamp()
+            .millis
+            .saturating_sub(COMMAND_TIMEOUT.as_millis() as u64);
+
+        self.pending
+            .lock()
+            .expect("Pending commands poisoned")
+            .retain(|_, command| command.issued_at.millis > deadline);
+    }
+}
+
+/// A registered bot, backed by a user that cannot log in with a password.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Bot {
+    pub user_id: String,
+    /// SHA-256 hash of the token secret.
+    pub token_hash: String,
+    /// Names of the slash commands this bot handles.
+    pub commands: Vec<String>,
+    pub created_at: Timestamp,
+}
+
+/// A slash command sent to a bot.
+#[derive(Clone, Debug)]
+pub struct Command {
+    pub command_id: String,
+    pub bot_id: String,
+    pub channel_id: String,
+    /// The user who issued the command.
+    pub user_id: String,
+    pub command: String,
+    pub args: String,
+    pub content: Content,
+    pub issued_at: Timestamp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_splits_args() {
+        assert_eq!(parse_command("/roll"), Some(("roll", "")));
+        assert_eq!(parse_command("/roll  2d6 + 1 "), Some(("roll", "2d6 + 1")));
+        assert_eq!(
+            parse_command("/set-topic_2\nnew"),
+            Some(("set-topic_2", "new"))
+        );
+    }
+
+    #[test]
+    fn parse_command_rejects_non_commands() {
+        for text in [
+            "roll", "/", "/ roll", "//roll", "/path/to", "/roll!", " /roll",
+        ] {
+            assert_eq!(parse_command(text), None, "{text}");
+        }
+    }
+}
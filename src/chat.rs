@@ -1,12 +1,28 @@
 use crate::database::Database;
 use crate::error::Error;
+use crate::events::ChannelEvent;
+use crate::state::ServerState;
+use crate::{config, filter, resource, user, utils, webhook};
 use elysium_rust::chat::v1::ChannelPermission;
 use elysium_rust::common::v1::ErrorCode;
-use elysium_rust::{Channel, Content, Message, Timestamp};
-use surrealdb::opt::PatchOp;
+use elysium_rust::user::v1::UserRole;
+use elysium_rust::{Channel, Content, Message, ResourceId, Timestamp};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+use surrealdb::types::SurrealValue;
+use ulid::{Generator, Ulid};
 
 pub const ID_LENGTH: usize = 10;
 
+pub const INVITE_CODE_LENGTH: usize = 8;
+
+/// Number of messages updated at once when storing the mentions of older messages.
+const MENTION_BACKFILL_BATCH_SIZE: usize = 500;
+
+static MESSAGE_IDS: Mutex<Generator> = Mutex::new(Generator::new());
+
 pub async fn create_channel(database: &Database, channel: Channel) -> Result<Channel, Error> {
     let channel: Option<Channel> = database
         .create(("channel", channel.channel_id.as_str()))
@@ -40,6 +56,16 @@ pub async fn get_channel_member_perm(
         .map_err(|_| Error::new(ErrorCode::Internal, "Failed to parse channel permission"))
 }
 
+pub async fn get_user_channels(database: &Database, user_id: &str) -> Result<Vec<Channel>, Error> {
+    let channels: Vec<Channel> = database
+        .query("SELECT * FROM channel WHERE $user IN object::keys(members);")
+        .bind(("user", user_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(channels)
+}
+
 pub async fn channel_exists(database: &Database, channel_id: &str) -> Result<bool, Error> {
     Ok(get_channel(database, channel_id).await?.is_some())
 }
@@ -54,39 +80,300 @@ pub async fn build_channel_id(database: &Database) -> Result<String, Error> {
     Ok(id)
 }
 
-pub async fn send(database: &Database, message: Message) -> Result<Message, Error> {
-    if !channel_exists(database, &message.channel_id).await? {
+/// Adds a user to the channel members with the given permission.
+pub async fn add_member(
+    database: &Database,
+    channel_id: &str,
+    user_id: &str,
+    perm: ChannelPermission,
+) -> Result<Channel, Error> {
+    if !channel_exists(database, channel_id).await? {
         return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
     }
 
-    let message: Option<Message> = database
-        .create(("message", message.message_id.as_str()))
-        .content(message)
+    let deliveries = webhook::build_deliveries(
+        database,
+        &ChannelEvent::MemberChanged {
+            channel_id: channel_id.to_string(),
+            user_id: user_id.to_string(),
+            permission: perm,
+        },
+    )
+    .await?;
+
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+UPDATE type::record('channel', $id) MERGE { members: $member };
+FOR $delivery IN $deliveries {
+    CREATE type::record('webhook_delivery', $delivery.delivery_id) CONTENT $delivery;
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", channel_id.to_string()))
+        .bind((
+            "member",
+            HashMap::from([(user_id.to_string(), perm as i32)]),
+        ))
+        .bind(("deliveries", deliveries))
+        .await?
+        .check()?;
+
+    get_channel(database, channel_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))
+}
+
+pub fn build_invite_code() -> String {
+    nanoid::nanoid!(INVITE_CODE_LENGTH)
+}
+
+pub async fn create_invite(database: &Database, invite: Invite) -> Result<Invite, Error> {
+    let invite: Option<Invite> = database
+        .create(("invite", invite.code.as_str()))
+        .content(invite)
         .await?;
-    let message = message.ok_or(Error::new(ErrorCode::Internal, "Failed to create message"))?;
+
+    invite.ok_or(Error::new(ErrorCode::Internal, "Failed to create invite"))
+}
+
+pub async fn get_invite(database: &Database, code: &str) -> Result<Option<Invite>, Error> {
+    let invite: Option<Invite> = database.select(("invite", code)).await?;
+
+    Ok(invite)
+}
+
+pub async fn get_channel_invites(
+    database: &Database,
+    channel_id: &str,
+) -> Result<Vec<Invite>, Error> {
+    let invites: Vec<Invite> = database
+        .query(
+            r#"
+SELECT *
+FROM invite
+WHERE channel_id = $channel
+ORDER BY created_at.millis DESC;
+"#,
+        )
+        .bind(("channel", channel_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(invites)
+}
+
+pub async fn revoke_invite(database: &Database, code: &str) -> Result<(), Error> {
+    let _: Option<Invite> = database.delete(("invite", code)).await?;
+
+    Ok(())
+}
+
+/// Counts one use of an invite, unless it is used up or expired.
+///
+/// Returns `None` if the invite cannot be used (anymore).
+pub async fn use_invite(
+    database: &Database,
+    code: &str,
+    now: Timestamp,
+) -> Result<Option<Invite>, Error> {
+    // Checked and counted in one statement, so concurrent joins cannot exceed max uses
+    let invite: Option<Invite> = database
+        .query(
+            r#"
+UPDATE type::record('invite', $code)
+SET uses += 1
+WHERE (max_uses = 0 OR uses < max_uses)
+  AND (expires_at.millis = 0 OR expires_at.millis > $now)
+RETURN AFTER;
+"#,
+        )
+        .bind(("code", code.to_string()))
+        .bind(("now", now.millis))
+        .await?
+        .take(0)?;
+
+    Ok(invite)
+}
+
+/// Stores a message, which is removed automatically at `expires_at` if given.
+///
+/// A message sent for a scheduled message removes it from the schedule in the same transaction,
+/// so it cannot be sent twice, and is not sent at all once the scheduled message is gone.
+pub async fn send(
+    database: &Database,
+    message: Message,
+    expires_at: Option<Timestamp>,
+    schedule_id: Option<String>,
+) -> Result<Message, Error> {
+    if !channel_exists(database, &message.channel_id).await? {
+        return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+    }
+
+    let deliveries =
+        webhook::build_deliveries(database, &ChannelEvent::MessageCreated(message.clone())).await?;
+
+    // Stored together, so neither the unread counters nor webhooks can miss the message
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+IF $schedule != NONE {
+    LET $scheduled = (DELETE ONLY type::record('scheduled_message', $schedule) RETURN BEFORE);
+    IF $scheduled = NONE {
+        THROW 'Scheduled message not found';
+    };
+};
+CREATE type::record('message', $id) CONTENT $message;
+UPDATE read_state SET unread += 1
+WHERE channel_id = $channel AND user_id != $user;
+UPDATE read_state SET mentions += 1
+WHERE channel_id = $channel AND user_id != $user AND user_id IN $mentions;
+FOR $delivery IN $deliveries {
+    CREATE type::record('webhook_delivery', $delivery.delivery_id) CONTENT $delivery;
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", message.message_id.clone()))
+        .bind(("message", MessageRecord::new(&message, expires_at)))
+        .bind(("schedule", schedule_id))
+        .bind(("channel", message.channel_id.clone()))
+        .bind(("user", message.user_id.clone()))
+        .bind(("mentions", mentions(&message.content)))
+        .bind(("deliveries", deliveries))
+        .await?
+        .check()?;
 
     Ok(message)
 }
 
-pub async fn read_messages(
+/// Sends a message, applies its lifetime and notifies channel subscribers.
+///
+/// The message passes the content filters first, which may reject or mask it and flag it for
+/// review, and then the slow mode of the channel. A `ttl` of `0` falls back to the default
+/// message lifetime of the channel.
+pub async fn post(state: &ServerState, message: Message, ttl: u64) -> Result<Message, Error> {
+    post_message(state, message, ttl, None).await
+}
+
+/// Posts the message of a scheduled message like [`post`], removing it from the schedule.
+pub async fn post_scheduled(
+    state: &ServerState,
+    message: Message,
+    ttl: u64,
+    schedule_id: &str,
+) -> Result<Message, Error> {
+    post_message(state, message, ttl, Some(schedule_id.to_string())).await
+}
+
+async fn post_message(
+    state: &ServerState,
+    mut message: Message,
+    ttl: u64,
+    schedule_id: Option<String>,
+) -> Result<Message, Error> {
+    let database = state.database();
+
+    let flagged = state
+        .filters()
+        .apply(&message.channel_id, &mut message.content)?;
+
+    check_slow_mode(state, &message.channel_id, &message.user_id).await?;
+
+    let ttl = if ttl > 0 {
+        ttl
+    } else {
+        get_channel_settings(database, &message.channel_id)
+            .await?
+            .message_ttl
+    };
+
+    // Stored with the message, so it cannot be left without its expiry
+    let expires_at = (ttl > 0).then(|| Timestamp {
+        millis: message.content.created_at.millis + ttl * 1000,
+    });
+
+    let message = send(database, message, expires_at, schedule_id).await?;
+
+    filter::flag(database, &message, &flagged).await?;
+
+    state
+        .events()
+        .publish(ChannelEvent::MessageCreated(message.clone()));
+
+    Ok(message)
+}
+
+/// Counts a post of a user against the slow mode of a channel.
+///
+/// Only checked once a message is otherwise valid, so a rejected message does not count.
+/// Managers, supervisors and authors who are no members, like incoming webhooks with their own
+/// rate limit, are exempt.
+pub async fn check_slow_mode(
+    state: &ServerState,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<(), Error> {
+    let database = state.database();
+    let settings = get_channel_settings(database, channel_id).await?;
+
+    if settings.slow_mode == 0 {
+        return Ok(());
+    }
+
+    let channel = get_channel(database, channel_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+    if channel
+        .members
+        .get(user_id)
+        .is_none_or(|perm| *perm == ChannelPermission::Manager as i32)
+    {
+        return Ok(());
+    }
+
+    if user::get(database, user_id)
+        .await?
+        .is_some_and(|user| user.role >= UserRole::Supervisor as i32)
+    {
+        return Ok(());
+    }
+
+    state
+        .rate_limiter()
+        .check(
+            &format!("slow_mode:{channel_id}:{user_id}"),
+            1,
+            Duration::from_secs(settings.slow_mode),
+        )
+        .map_err(Error::rate_limited)
+}
+
+pub fn can_send(perm: ChannelPermission) -> bool {
+    perm == ChannelPermission::ReadWrite || perm == ChannelPermission::Manager
+}
+
+/// Returns up to `limit` messages that expired at or before `now`.
+pub async fn get_expired(
     database: &Database,
-    channel_id: String,
-    limit: u32,
-    start_at: Timestamp,
+    now: Timestamp,
+    limit: usize,
 ) -> Result<Vec<Message>, Error> {
     let messages: Vec<Message> = database
         .query(
             r#"
 SELECT *
 FROM message
-WHERE channel_id = $channel
-  AND content.created_at.millis < $cursor
-ORDER BY timestamp DESC
+WHERE expires_at != NONE AND expires_at <= $now
+ORDER BY expires_at ASC
 LIMIT $limit;
 "#,
         )
-        .bind(("channel", channel_id))
-        .bind(("cursor", start_at))
+        .bind(("now", now.millis))
         .bind(("limit", limit))
         .await?
         .take(0)?;
@@ -94,31 +381,414 @@ LIMIT $limit;
     Ok(messages)
 }
 
-pub async fn delete_message(database: &Database, message_id: &str) -> Result<(), Error> {
-    if msg_exists(database, message_id).await? {
-        let _: Option<Message> = database.delete(("message", message_id)).await?;
+/// Returns the earliest time at which a message expires.
+pub async fn next_expiry(database: &Database) -> Result<Option<Timestamp>, Error> {
+    let expiry: Option<Expiry> = database
+        .query(
+            r#"
+SELECT expires_at
+FROM message
+WHERE expires_at != NONE
+ORDER BY expires_at ASC
+LIMIT 1;
+"#,
+        )
+        .await?
+        .take(0)?;
+
+    Ok(expiry.map(|expiry| Timestamp {
+        millis: expiry.expires_at,
+    }))
+}
+
+pub fn build_schedule_id() -> String {
+    Ulid::new().to_string()
+}
+
+pub async fn schedule_message(
+    database: &Database,
+    scheduled: ScheduledMessage,
+) -> Result<ScheduledMessage, Error> {
+    let scheduled: Option<ScheduledMessage> = database
+        .create(("scheduled_message", scheduled.schedule_id.as_str()))
+        .content(scheduled)
+        .await?;
+
+    scheduled.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to schedule message",
+    ))
+}
+
+pub async fn get_scheduled(
+    database: &Database,
+    schedule_id: &str,
+) -> Result<Option<ScheduledMessage>, Error> {
+    let scheduled: Option<ScheduledMessage> =
+        database.select(("scheduled_message", schedule_id)).await?;
+
+    Ok(scheduled)
+}
 
-        Ok(())
+pub async fn get_user_scheduled(
+    database: &Database,
+    user_id: &str,
+) -> Result<Vec<ScheduledMessage>, Error> {
+    let scheduled: Vec<ScheduledMessage> = database
+        .query(
+            r#"
+SELECT *
+FROM scheduled_message
+WHERE user_id = $user
+ORDER BY send_at.millis ASC;
+"#,
+        )
+        .bind(("user", user_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(scheduled)
+}
+
+pub async fn update_scheduled(
+    database: &Database,
+    scheduled: ScheduledMessage,
+) -> Result<ScheduledMessage, Error> {
+    let scheduled: Option<ScheduledMessage> = database
+        .update(("scheduled_message", scheduled.schedule_id.as_str()))
+        .content(scheduled)
+        .await?;
+
+    scheduled.ok_or(Error::new(
+        ErrorCode::NotFound,
+        "Scheduled message not found",
+    ))
+}
+
+/// Moves the due time of a scheduled message to retry a failed delivery, counting the attempt.
+pub async fn postpone_scheduled(
+    database: &Database,
+    schedule_id: &str,
+    send_at: Timestamp,
+) -> Result<(), Error> {
+    database
+        .query(
+            "UPDATE type::record('scheduled_message', $id) SET send_at = $send_at, attempts += 1;",
+        )
+        .bind(("id", schedule_id.to_string()))
+        .bind(("send_at", send_at))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+pub async fn cancel_scheduled(database: &Database, schedule_id: &str) -> Result<(), Error> {
+    let scheduled: Option<ScheduledMessage> =
+        database.delete(("scheduled_message", schedule_id)).await?;
+
+    scheduled.map(|_| ()).ok_or(Error::new(
+        ErrorCode::NotFound,
+        "Scheduled message not found",
+    ))
+}
+
+/// Returns up to `limit` scheduled messages that are due at `now`.
+pub async fn get_due_scheduled(
+    database: &Database,
+    now: Timestamp,
+    limit: usize,
+) -> Result<Vec<ScheduledMessage>, Error> {
+    let scheduled: Vec<ScheduledMessage> = database
+        .query(
+            r#"
+SELECT *
+FROM scheduled_message
+WHERE send_at.millis <= $now
+ORDER BY send_at.millis ASC
+LIMIT $limit;
+"#,
+        )
+        .bind(("now", now.millis))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(scheduled)
+}
+
+/// Returns the earliest time at which a scheduled message is due.
+pub async fn next_scheduled(database: &Database) -> Result<Option<Timestamp>, Error> {
+    let scheduled: Option<ScheduledMessage> = database
+        .query("SELECT * FROM scheduled_message ORDER BY send_at.millis ASC LIMIT 1;")
+        .await?
+        .take(0)?;
+
+    Ok(scheduled.map(|scheduled| scheduled.send_at))
+}
+
+pub async fn get_channel_settings(
+    database: &Database,
+    channel_id: &str,
+) -> Result<ChannelSettings, Error> {
+    let settings: Option<ChannelSettings> =
+        database.select(("channel_settings", channel_id)).await?;
+
+    Ok(settings.unwrap_or_else(|| ChannelSettings {
+        channel_id: channel_id.to_string(),
+        message_ttl: 0,
+        slow_mode: 0,
+    }))
+}
+
+pub async fn update_channel_settings(
+    database: &Database,
+    settings: ChannelSettings,
+) -> Result<ChannelSettings, Error> {
+    if !channel_exists(database, &settings.channel_id).await? {
+        return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+    }
+
+    let settings: Option<ChannelSettings> = database
+        .upsert(("channel_settings", settings.channel_id.as_str()))
+        .content(settings)
+        .await?;
+
+    settings.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to update channel settings",
+    ))
+}
+
+pub async fn read_messages(
+    database: &Database,
+    channel_id: String,
+    limit: u32,
+    cursor: Cursor,
+    direction: Direction,
+) -> Result<(Vec<Message>, Option<Cursor>), Error> {
+    let query = match direction {
+        Direction::Backward => {
+            r#"
+SELECT *
+FROM message
+WHERE channel_id = $channel
+  AND (content.created_at.millis < $millis
+    OR (content.created_at.millis = $millis AND message_id < $id))
+ORDER BY content.created_at.millis DESC, message_id DESC
+LIMIT $limit;
+"#
+        }
+
+        Direction::Forward => {
+            r#"
+SELECT *
+FROM message
+WHERE channel_id = $channel
+  AND (content.created_at.millis > $millis
+    OR (content.created_at.millis = $millis AND message_id > $id))
+ORDER BY content.created_at.millis ASC, message_id ASC
+LIMIT $limit;
+"#
+        }
+    };
+
+    let messages: Vec<Message> = database
+        .query(query)
+        .bind(("channel", channel_id))
+        .bind(("millis", cursor.created_at))
+        .bind(("id", cursor.message_id))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    // A short page means there is nothing left in this direction
+    let next = if messages.len() < limit as usize {
+        None
+    } else {
+        messages.last().map(Cursor::from)
+    };
+
+    Ok((messages, next))
+}
+
+/// Deletes a message, returning its pin if the message was pinned.
+pub async fn delete_message(database: &Database, message_id: &str) -> Result<Option<Pin>, Error> {
+    if let Some(message) = get_msg(database, message_id).await? {
+        let deliveries = webhook::build_deliveries(
+            database,
+            &ChannelEvent::MessageDeleted {
+                channel_id: message.channel_id.clone(),
+                message_id: message.message_id.clone(),
+            },
+        )
+        .await?;
+
+        // Only users who have not read the message yet still count it as unread
+        let mut response = database
+            .query(
+                r#"
+BEGIN TRANSACTION;
+DELETE type::record('message', $message);
+DELETE message_revision WHERE message_id = $message;
+LET $pin = (DELETE ONLY type::record('pin', $message) RETURN BEFORE);
+UPDATE read_state SET unread -= 1
+WHERE channel_id = $channel AND user_id != $user AND unread > 0
+  AND (read_at.millis < $created OR (read_at.millis = $created AND message_id < $message));
+UPDATE read_state SET mentions -= 1
+WHERE channel_id = $channel AND user_id != $user AND mentions > 0 AND user_id IN $mentions
+  AND (read_at.millis < $created OR (read_at.millis = $created AND message_id < $message));
+FOR $delivery IN $deliveries {
+    CREATE type::record('webhook_delivery', $delivery.delivery_id) CONTENT $delivery;
+};
+RETURN $pin;
+COMMIT TRANSACTION;
+"#,
+            )
+            .bind(("channel", message.channel_id.clone()))
+            .bind(("user", message.user_id.clone()))
+            .bind(("created", message.content.created_at.millis))
+            .bind(("message", message.message_id.clone()))
+            .bind(("mentions", mentions(&message.content)))
+            .bind(("deliveries", deliveries))
+            .await?
+            .check()?;
+
+        let last = response.num_statements() - 1;
+        let pin: Option<Pin> = response.take(last)?;
+
+        if config::get().service_delete_attachments {
+            delete_attachments(database, &message.content.attachments).await?;
+        }
+
+        Ok(pin)
     } else {
         Err(Error::new(ErrorCode::NotFound, "Message not found"))
     }
 }
 
+async fn delete_attachments(database: &Database, attachments: &[ResourceId]) -> Result<(), Error> {
+    for attachment in attachments {
+        // Keep attachments that other messages still refer to
+        let references: Option<usize> = database
+            .query(
+                "SELECT VALUE count() FROM message WHERE $attachment IN content.attachments GROUP ALL;",
+            )
+            .bind(("attachment", attachment.clone()))
+            .await?
+            .take(0)?;
+
+        if references.unwrap_or(0) == 0 && resource::exists(database, attachment).await? {
+            resource::delete(database, attachment).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the content of a message and records the time of the edit.
+///
+/// The replaced content is kept as a [`MessageRevision`] until the message is deleted.
+/// The creation time of the message is kept, since messages are ordered and paged by it.
+/// Attachments removed by the edit are deleted like those of deleted messages.
 pub async fn update_message(
     database: &Database,
     message_id: &str,
-    content: Content,
+    mut content: Content,
 ) -> Result<Message, Error> {
-    if msg_exists(database, message_id).await? {
-        let message: Option<Message> = database
-            .update(("message", message_id))
-            .patch(PatchOp::replace("/content", content))
-            .await?;
+    let message = get_msg(database, message_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Message not found"))?;
 
-        message.ok_or(Error::new(ErrorCode::Internal, "Failed to update message"))
-    } else {
-        Err(Error::new(ErrorCode::NotFound, "Message not found"))
+    content.created_at = message.content.created_at.clone();
+
+    let now = utils::get_timestamp();
+    let revision = MessageRevision {
+        message_id: message.message_id.clone(),
+        content: message.content.clone(),
+        replaced_at: now.clone(),
+    };
+
+    let detached: Vec<ResourceId> = message
+        .content
+        .attachments
+        .iter()
+        .filter(|attachment| !content.attachments.contains(attachment))
+        .cloned()
+        .collect();
+
+    let previous = mentions(&message.content);
+    let message = Message { content, ..message };
+    let current = mentions(&message.content);
+
+    // Users still mentioned after the edit were already counted
+    let added: Vec<String> = current
+        .iter()
+        .filter(|user| !previous.contains(user))
+        .cloned()
+        .collect();
+    let removed: Vec<String> = previous
+        .iter()
+        .filter(|user| !current.contains(user))
+        .cloned()
+        .collect();
+
+    let deliveries =
+        webhook::build_deliveries(database, &ChannelEvent::MessageUpdated(message.clone())).await?;
+
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+UPDATE type::record('message', $id) SET content = $content, mentions = $mentions, edited_at = $now;
+CREATE message_revision CONTENT $revision;
+UPDATE read_state SET mentions += 1
+WHERE channel_id = $channel AND user_id != $user AND user_id IN $added
+  AND (read_at.millis < $created OR (read_at.millis = $created AND message_id < $id));
+UPDATE read_state SET mentions -= 1
+WHERE channel_id = $channel AND user_id != $user AND mentions > 0 AND user_id IN $removed
+  AND (read_at.millis < $created OR (read_at.millis = $created AND message_id < $id));
+FOR $delivery IN $deliveries {
+    CREATE type::record('webhook_delivery', $delivery.delivery_id) CONTENT $delivery;
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", message.message_id.clone()))
+        .bind(("content", message.content.clone()))
+        .bind(("mentions", current))
+        .bind(("channel", message.channel_id.clone()))
+        .bind(("user", message.user_id.clone()))
+        .bind(("created", message.content.created_at.millis))
+        .bind(("added", added))
+        .bind(("removed", removed))
+        .bind(("now", now.millis))
+        .bind(("revision", revision))
+        .bind(("deliveries", deliveries))
+        .await?
+        .check()?;
+
+    if config::get().service_delete_attachments {
+        delete_attachments(database, &detached).await?;
     }
+
+    Ok(message)
+}
+
+/// Returns the earlier revisions of the given messages, oldest first.
+pub async fn get_revisions(
+    database: &Database,
+    message_ids: Vec<String>,
+) -> Result<Vec<MessageRevision>, Error> {
+    let revisions: Vec<MessageRevision> = database
+        .query(
+            "SELECT * FROM message_revision WHERE message_id IN $messages ORDER BY replaced_at.millis;",
+        )
+        .bind(("messages", message_ids))
+        .await?
+        .take(0)?;
+
+    Ok(revisions)
 }
 
 pub async fn get_msg(database: &Database, message_id: &str) -> Result<Option<Message>, Error> {
@@ -131,12 +801,499 @@ pub async fn msg_exists(database: &Database, message_id: &str) -> Result<bool, E
     Ok(get_msg(database, message_id).await?.is_some())
 }
 
-pub async fn build_message_id(database: &Database) -> Result<String, Error> {
-    let mut id = nanoid::nanoid!(ID_LENGTH);
+/// Builds a new message ID.
+///
+/// Message IDs are monotonic ULIDs, so they sort in creation order.
+pub fn build_message_id() -> String {
+    MESSAGE_IDS
+        .lock()
+        .expect("Message ID generator poisoned")
+        .generate()
+        .map(|id| id.to_string())
+        .unwrap_or_else(|_| Ulid::new().to_string())
+}
 
-    while msg_exists(database, &id).await? {
-        id = nanoid::nanoid!(ID_LENGTH);
+pub async fn pin_message(database: &Database, pin: Pin) -> Result<Pin, Error> {
+    if get_pin(database, &pin.message_id).await?.is_some() {
+        return Err(Error::new(
+            ErrorCode::AlreadyExists,
+            "Message is already pinned",
+        ));
     }
 
-    Ok(id)
+    // Checked and created in one statement, so concurrent pins cannot exceed the limit
+    let pin: Option<Pin> = database
+        .query(
+            r#"
+IF count(SELECT VALUE message_id FROM pin WHERE channel_id = $channel) < $max {
+    CREATE ONLY type::record('pin', $id) CONTENT $pin
+};
+"#,
+        )
+        .bind(("channel", pin.channel_id.clone()))
+        .bind(("max", config::get().service_max_channel_pins))
+        .bind(("id", pin.message_id.clone()))
+        .bind(("pin", pin))
+        .await?
+        .take(0)?;
+
+    pin.ok_or(Error::new(
+        ErrorCode::InvalidFormat,
+        "Maximum number of pinned messages reached",
+    ))
+}
+
+pub async fn unpin_message(database: &Database, message_id: &str) -> Result<(), Error> {
+    let pin: Option<Pin> = database.delete(("pin", message_id)).await?;
+
+    pin.map(|_| ())
+        .ok_or(Error::new(ErrorCode::NotFound, "Message is not pinned"))
+}
+
+pub async fn get_pin(database: &Database, message_id: &str) -> Result<Option<Pin>, Error> {
+    let pin: Option<Pin> = database.select(("pin", message_id)).await?;
+
+    Ok(pin)
+}
+
+pub async fn get_pinned_messages(
+    database: &Database,
+    channel_id: String,
+) -> Result<Vec<Message>, Error> {
+    let messages: Vec<Message> = database
+        .query(
+            r#"
+SELECT *
+FROM message
+WHERE channel_id = $channel
+  AND message_id IN (SELECT VALUE message_id FROM pin WHERE channel_id = $channel)
+ORDER BY content.created_at.millis DESC, message_id DESC;
+"#,
+        )
+        .bind(("channel", channel_id))
+        .await?
+        .take(0)?;
+
+    Ok(messages)
+}
+
+/// Returns the IDs of all users mentioned with `@<user_id>` in the given content, each once.
+pub fn mentions(content: &Content) -> Vec<String> {
+    let mut users: Vec<String> = Vec::new();
+
+    for user in content
+        .text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|user| user.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_'))
+        .filter(|user| !user.is_empty())
+    {
+        if !users.iter().any(|known| known == user) {
+            users.push(user.to_string());
+        }
+    }
+
+    users
+}
+
+/// Stores the mentions of messages sent before they were stored with the message.
+pub async fn backfill_mentions(database: &Database) -> Result<(), Error> {
+    loop {
+        let messages: Vec<Message> = database
+            .query("SELECT * FROM message WHERE mentions IS NONE LIMIT $limit;")
+            .bind(("limit", MENTION_BACKFILL_BATCH_SIZE))
+            .await?
+            .take(0)?;
+
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        for message in messages {
+            database
+                .query("UPDATE type::record('message', $id) SET mentions = $mentions;")
+                .bind(("id", message.message_id.clone()))
+                .bind(("mentions", mentions(&message.content)))
+                .await?
+                .check()?;
+        }
+    }
+}
+
+pub async fn mark_read(
+    database: &Database,
+    user_id: &str,
+    message: &Message,
+) -> Result<ReadState, Error> {
+    let state = count_unread(
+        database,
+        ReadState {
+            channel_id: message.channel_id.clone(),
+            user_id: user_id.to_string(),
+            message_id: message.message_id.clone(),
+            read_at: message.content.created_at.clone(),
+            unread: 0,
+            mentions: 0,
+        },
+    )
+    .await?;
+
+    let state: Option<ReadState> = database
+        .upsert(("read_state", read_state_id(&state.channel_id, user_id)))
+        .content(state)
+        .await?;
+
+    state.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to update read marker",
+    ))
+}
+
+/// Returns the read states of a user in the given channels, in the same order.
+pub async fn get_read_states(
+    database: &Database,
+    channel_ids: Vec<String>,
+    user_id: &str,
+) -> Result<Vec<ReadState>, Error> {
+    let stored: Vec<ReadState> = database
+        .query("SELECT * FROM read_state WHERE channel_id IN $channels AND user_id = $user;")
+        .bind(("channels", channel_ids.clone()))
+        .bind(("user", user_id.to_string()))
+        .await?
+        .take(0)?;
+
+    let missing: Vec<String> = channel_ids
+        .iter()
+        .filter(|channel_id| !stored.iter().any(|state| &state.channel_id == *channel_id))
+        .cloned()
+        .collect();
+
+    let mut created = Vec::new();
+
+    if !missing.is_empty() {
+        // The user never read anything in these channels, so count everything once and keep
+        // the counters up to date from now on.
+        let counts: Vec<ChannelUnreadCounts> = database
+            .query(
+                r#"
+SELECT channel_id, count() AS unread, count($user IN mentions) AS mentions
+FROM message
+WHERE channel_id IN $channels
+  AND user_id != $user
+GROUP BY channel_id;
+"#,
+            )
+            .bind(("channels", missing.clone()))
+            .bind(("user", user_id.to_string()))
+            .await?
+            .take(0)?;
+
+        created = missing
+            .into_iter()
+            .map(|channel_id| {
+                let counts = counts.iter().find(|counts| counts.channel_id == channel_id);
+
+                ReadState {
+                    channel_id,
+                    user_id: user_id.to_string(),
+                    message_id: String::new(),
+                    read_at: Timestamp { millis: 0 },
+                    unread: counts.map_or(0, |counts| counts.unread),
+                    mentions: counts.map_or(0, |counts| counts.mentions),
+                }
+            })
+            .collect();
+
+        database
+            .query(
+                r#"
+FOR $state IN $states {
+    UPSERT type::record('read_state', $state.channel_id + ':' + $state.user_id) CONTENT $state;
+};
+"#,
+            )
+            .bind(("states", created.clone()))
+            .await?
+            .check()?;
+    }
+
+    Ok(channel_ids
+        .iter()
+        .filter_map(|channel_id| {
+            stored
+                .iter()
+                .chain(created.iter())
+                .find(|state| &state.channel_id == channel_id)
+                .cloned()
+        })
+        .collect())
+}
+
+async fn count_unread(database: &Database, mut state: ReadState) -> Result<ReadState, Error> {
+    let counts: Option<UnreadCounts> = database
+        .query(
+            r#"
+SELECT count() AS unread, count($user IN mentions) AS mentions
+FROM message
+WHERE channel_id = $channel
+  AND user_id != $user
+  AND (content.created_at.millis > $read_at
+    OR (content.created_at.millis = $read_at AND message_id > $message))
+GROUP ALL;
+"#,
+        )
+        .bind(("channel", state.channel_id.clone()))
+        .bind(("user", state.user_id.clone()))
+        .bind(("read_at", state.read_at.millis))
+        .bind(("message", state.message_id.clone()))
+        .await?
+        .take(0)?;
+
+    let counts = counts.unwrap_or(UnreadCounts {
+        unread: 0,
+        mentions: 0,
+    });
+
+    state.unread = counts.unread;
+    state.mentions = counts.mentions;
+
+    Ok(state)
+}
+
+fn read_state_id(channel_id: &str, user_id: &str) -> String {
+    format!("{channel_id}:{user_id}")
+}
+
+/// A message that is sent by the scheduler once `send_at` is reached.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct ScheduledMessage {
+    pub schedule_id: String,
+    pub user_id: String,
+    pub channel_id: String,
+    pub content: Content,
+    pub send_at: Timestamp,
+    /// Lifetime of the sent message in seconds, `0` for the channel default.
+    pub ttl: u64,
+    /// Number of failed deliveries so far.
+    pub attempts: u32,
+}
+
+/// Server-side settings of a channel.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct ChannelSettings {
+    pub channel_id: String,
+    /// Default lifetime of new messages in seconds, `0` to keep them forever.
+    pub message_ttl: u64,
+    /// Minimum time in seconds between two messages of a member, `0` to disable slow mode.
+    pub slow_mode: u64,
+}
+
+/// An invite code which adds its users to a channel.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Invite {
+    pub code: String,
+    pub channel_id: String,
+    /// The `ChannelPermission` granted to users joining with this invite.
+    pub permission: i32,
+    /// Maximum number of uses, `0` for unlimited.
+    pub max_uses: u32,
+    pub uses: u32,
+    /// Time at which the invite expires, `0` if it never does.
+    pub expires_at: Timestamp,
+    pub created_by: String,
+    pub created_at: Timestamp,
+}
+
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Pin {
+    pub channel_id: String,
+    pub message_id: String,
+    /// The user who pinned the message.
+    pub user_id: String,
+    pub pinned_at: Timestamp,
+}
+
+/// Content of a message before one of its edits.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct MessageRevision {
+    pub message_id: String,
+    pub content: Content,
+    /// Time of the edit that replaced this content.
+    pub replaced_at: Timestamp,
+}
+
+/// Per-user read marker of a channel, including the cached unread counters.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct ReadState {
+    pub channel_id: String,
+    pub user_id: String,
+    /// ID of the last read message, empty if nothing was read yet.
+    pub message_id: String,
+    /// Creation time of the last read message.
+    pub read_at: Timestamp,
+    pub unread: u64,
+    pub mentions: u64,
+}
+
+/// A message as stored in the database, with the fields only the server uses.
+#[derive(Clone, Debug, SurrealValue)]
+struct MessageRecord {
+    message_id: String,
+    user_id: String,
+    channel_id: String,
+    content: Content,
+    /// Time in milliseconds at which the message is removed, `None` to keep it forever.
+    expires_at: Option<u64>,
+    /// Time in milliseconds of the last edit, `None` if the message was never edited.
+    edited_at: Option<u64>,
+    /// IDs of the mentioned users, see [`mentions`].
+    mentions: Vec<String>,
+}
+
+impl MessageRecord {
+    fn new(message: &Message, expires_at: Option<Timestamp>) -> Self {
+        Self {
+            message_id: message.message_id.clone(),
+            user_id: message.user_id.clone(),
+            channel_id: message.channel_id.clone(),
+            content: message.content.clone(),
+            expires_at: expires_at.map(|expires_at| expires_at.millis),
+            edited_at: None,
+            mentions: mentions(&message.content),
+        }
+    }
+}
+
+#[derive(Clone, Debug, SurrealValue)]
+struct Expiry {
+    expires_at: u64,
+}
+
+#[derive(Clone, Debug, SurrealValue)]
+struct UnreadCounts {
+    unread: u64,
+    mentions: u64,
+}
+
+#[derive(Clone, Debug, SurrealValue)]
+struct ChannelUnreadCounts {
+    channel_id: String,
+    unread: u64,
+    mentions: u64,
+}
+
+/// Pagination position in a channel, ordered by creation time and message ID.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cursor {
+    pub created_at: u64,
+    pub message_id: String,
+}
+
+impl Cursor {
+    /// Cursor positioned before the oldest message.
+    pub fn start() -> Self {
+        Self {
+            created_at: 0,
+            message_id: String::new(),
+        }
+    }
+
+    /// Cursor positioned after the newest message.
+    pub fn end() -> Self {
+        Self {
+            // SurrealDB integers are signed
+            created_at: i64::MAX as u64,
+            message_id: String::new(),
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let (created_at, message_id) = s.split_once('.').ok_or(Error::invalid_argument())?;
+
+        Ok(Self {
+            created_at: created_at.parse().map_err(|_| Error::invalid_argument())?,
+            message_id: message_id.to_string(),
+        })
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.created_at, self.message_id)
+    }
+}
+
+impl From<&Message> for Cursor {
+    fn from(message: &Message) -> Self {
+        Self {
+            created_at: message.content.created_at.millis,
+            message_id: message.message_id.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Towards older messages.
+    Backward,
+    /// Towards newer messages.
+    Forward,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Content {
+        Content {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mentions_strip_punctuation() {
+        assert_eq!(
+            mentions(&text("hi @alice, @bob-2! and @carol_c.")),
+            ["alice", "bob-2", "carol_c"]
+        );
+    }
+
+    #[test]
+    fn mentions_keep_first_appearance_once() {
+        assert_eq!(
+            mentions(&text("@bob @alice @bob. @alice")),
+            ["bob", "alice"]
+        );
+    }
+
+    #[test]
+    fn mentions_ignore_non_mentions() {
+        assert!(mentions(&text("mail@example.com @ @! plain")).is_empty());
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            created_at: 1700000000000,
+            message_id: "01HF0000000000000000000000".to_string(),
+        };
+
+        assert_eq!(Cursor::parse(&cursor.to_string()).unwrap(), cursor);
+        assert_eq!(
+            Cursor::parse(&Cursor::end().to_string()).unwrap(),
+            Cursor::end()
+        );
+    }
+
+    #[test]
+    fn cursor_rejects_invalid() {
+        for invalid in ["", "1700000000000", "abc.01HF", "-1.01HF", ".01HF"] {
+            assert_eq!(
+                Cursor::parse(invalid).unwrap_err().code(),
+                ErrorCode::InvalidFormat,
+                "{invalid}"
+            );
+        }
+    }
 }
@@ -35,9 +35,23 @@ pub fn init() {
             .expect("Failed to create resource directory");
     }
 
+    if !std::fs::exists(&config.service_staging_dir)
+        .expect("Failed to check if staging directory exists")
+    {
+        std::fs::create_dir(&config.service_staging_dir)
+            .expect("Failed to create staging directory");
+    }
+
     CONFIG.set(config).expect("Failed to set config");
 }
 
+/// Initializes the default config once, shared by all tests of the crate.
+#[cfg(test)]
+pub fn init_test() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(init);
+}
+
 pub fn get<'a>() -> &'a Config {
     CONFIG.get().expect("Failed to get config")
 }
@@ -51,6 +65,43 @@ pub struct Config {
     pub service_allow_message_delete: i32,
     pub service_allow_message_update: i32,
     pub service_resource_dir: String,
+    pub service_staging_dir: String,
+    pub service_store: String,
+    pub service_max_resource_size: u64,
+    pub service_user_quota: u64,
+    pub service_supervisor_quota: u64,
+    pub service_admin_quota: u64,
+    pub service_channel_quota: u64,
+    pub service_max_image_size: u64,
+    pub service_max_image_dimension: u32,
+    pub service_max_avatar_dimension: u32,
+    pub service_avatar_allowed_types: String,
+    pub service_avatar_denied_types: String,
+    pub service_channel_allowed_types: String,
+    pub service_channel_denied_types: String,
+    pub service_builtin_allowed_types: String,
+    pub service_builtin_denied_types: String,
+    pub service_s3_endpoint: String,
+    pub service_s3_region: String,
+    pub service_s3_bucket: String,
+    pub service_s3_access_key: String,
+    pub service_s3_secret_key: String,
+    pub service_s3_allow_http: bool,
+    pub service_max_channel_pins: usize,
+    pub service_delete_attachments: bool,
+    pub service_expiry_interval: u64,
+    pub service_schedule_interval: u64,
+    pub service_presence_timeout: u64,
+    pub service_presence_idle: u64,
+    pub service_webhook_interval: u64,
+    pub service_webhook_timeout: u64,
+    pub service_webhook_max_attempts: u32,
+    pub service_webhook_rate_limit: u32,
+    pub service_webhook_allowed_hosts: String,
+    pub service_filter_interval: u64,
+    pub service_upload_session_ttl: u64,
+    pub service_gc_interval: u64,
+    pub service_gc_grace_period: u64,
     pub net_address: String,
     pub net_rate_limit_replenish: u64,
     pub net_rate_limit_burst: u32,
@@ -82,6 +133,9 @@ impl Config {
             .get_table("service")
             .expect("Failed parsing 'service' table");
 
+        // Fields added after the first release are optional, so existing config files keep working
+        let defaults = Self::default();
+
         let service_public_key = service
             .get_string("public_key")
             .expect("Failed parsing 'service.public_key' field")
@@ -112,6 +166,384 @@ impl Config {
             .expect("Failed parsing 'service.resource_dir' field")
             .to_string();
 
+        let service_staging_dir = service
+            .get("staging_dir")
+            .map(|_| {
+                service
+                    .get_string("staging_dir")
+                    .expect("Failed parsing 'service.staging_dir' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_staging_dir);
+
+        let service_store = service
+            .get("store")
+            .map(|_| {
+                service
+                    .get_string("store")
+                    .expect("Failed parsing 'service.store' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_store);
+
+        let service_max_resource_size = service
+            .get("max_resource_size")
+            .map(|_| {
+                service
+                    .get_integer("max_resource_size")
+                    .expect("Failed parsing 'service.max_resource_size' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_max_resource_size);
+
+        // Resource sizes are returned to clients as i32
+        if service_max_resource_size > i32::MAX as u64 {
+            panic!(
+                "'service.max_resource_size' cannot exceed {} bytes",
+                i32::MAX
+            );
+        }
+
+        let service_user_quota = service
+            .get("user_quota")
+            .map(|_| {
+                service
+                    .get_integer("user_quota")
+                    .expect("Failed parsing 'service.user_quota' field") as u64
+            })
+            .unwrap_or(defaults.service_user_quota);
+
+        let service_supervisor_quota = service
+            .get("supervisor_quota")
+            .map(|_| {
+                service
+                    .get_integer("supervisor_quota")
+                    .expect("Failed parsing 'service.supervisor_quota' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_supervisor_quota);
+
+        let service_admin_quota = service
+            .get("admin_quota")
+            .map(|_| {
+                service
+                    .get_integer("admin_quota")
+                    .expect("Failed parsing 'service.admin_quota' field") as u64
+            })
+            .unwrap_or(defaults.service_admin_quota);
+
+        let service_channel_quota = service
+            .get("channel_quota")
+            .map(|_| {
+                service
+                    .get_integer("channel_quota")
+                    .expect("Failed parsing 'service.channel_quota' field") as u64
+            })
+            .unwrap_or(defaults.service_channel_quota);
+
+        let service_max_image_size = service
+            .get("max_image_size")
+            .map(|_| {
+                service
+                    .get_integer("max_image_size")
+                    .expect("Failed parsing 'service.max_image_size' field") as u64
+            })
+            .unwrap_or(defaults.service_max_image_size);
+
+        let service_max_image_dimension = service
+            .get("max_image_dimension")
+            .map(|_| {
+                service
+                    .get_integer("max_image_dimension")
+                    .expect("Failed parsing 'service.max_image_dimension' field")
+                    as u32
+            })
+            .unwrap_or(defaults.service_max_image_dimension);
+
+        let service_max_avatar_dimension = service
+            .get("max_avatar_dimension")
+            .map(|_| {
+                service
+                    .get_integer("max_avatar_dimension")
+                    .expect("Failed parsing 'service.max_avatar_dimension' field")
+                    as u32
+            })
+            .unwrap_or(defaults.service_max_avatar_dimension);
+
+        let service_avatar_allowed_types = service
+            .get("avatar_allowed_types")
+            .map(|_| {
+                service
+                    .get_string("avatar_allowed_types")
+                    .expect("Failed parsing 'service.avatar_allowed_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_avatar_allowed_types);
+
+        let service_avatar_denied_types = service
+            .get("avatar_denied_types")
+            .map(|_| {
+                service
+                    .get_string("avatar_denied_types")
+                    .expect("Failed parsing 'service.avatar_denied_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_avatar_denied_types);
+
+        let service_channel_allowed_types = service
+            .get("channel_allowed_types")
+            .map(|_| {
+                service
+                    .get_string("channel_allowed_types")
+                    .expect("Failed parsing 'service.channel_allowed_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_channel_allowed_types);
+
+        let service_channel_denied_types = service
+            .get("channel_denied_types")
+            .map(|_| {
+                service
+                    .get_string("channel_denied_types")
+                    .expect("Failed parsing 'service.channel_denied_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_channel_denied_types);
+
+        let service_builtin_allowed_types = service
+            .get("builtin_allowed_types")
+            .map(|_| {
+                service
+                    .get_string("builtin_allowed_types")
+                    .expect("Failed parsing 'service.builtin_allowed_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_builtin_allowed_types);
+
+        let service_builtin_denied_types = service
+            .get("builtin_denied_types")
+            .map(|_| {
+                service
+                    .get_string("builtin_denied_types")
+                    .expect("Failed parsing 'service.builtin_denied_types' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_builtin_denied_types);
+
+        let service_s3_endpoint = service
+            .get("s3_endpoint")
+            .map(|_| {
+                service
+                    .get_string("s3_endpoint")
+                    .expect("Failed parsing 'service.s3_endpoint' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_s3_endpoint);
+
+        let service_s3_region = service
+            .get("s3_region")
+            .map(|_| {
+                service
+                    .get_string("s3_region")
+                    .expect("Failed parsing 'service.s3_region' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_s3_region);
+
+        let service_s3_bucket = service
+            .get("s3_bucket")
+            .map(|_| {
+                service
+                    .get_string("s3_bucket")
+                    .expect("Failed parsing 'service.s3_bucket' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_s3_bucket);
+
+        let service_s3_access_key = service
+            .get("s3_access_key")
+            .map(|_| {
+                service
+                    .get_string("s3_access_key")
+                    .expect("Failed parsing 'service.s3_access_key' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_s3_access_key);
+
+        let service_s3_secret_key = service
+            .get("s3_secret_key")
+            .map(|_| {
+                service
+                    .get_string("s3_secret_key")
+                    .expect("Failed parsing 'service.s3_secret_key' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_s3_secret_key);
+
+        let service_s3_allow_http = service
+            .get("s3_allow_http")
+            .map(|_| {
+                service
+                    .get_boolean("s3_allow_http")
+                    .expect("Failed parsing 'service.s3_allow_http' field")
+            })
+            .unwrap_or(defaults.service_s3_allow_http);
+
+        let service_max_channel_pins = service
+            .get("max_channel_pins")
+            .map(|_| {
+                service
+                    .get_integer("max_channel_pins")
+                    .expect("Failed parsing 'service.max_channel_pins' field")
+                    as usize
+            })
+            .unwrap_or(defaults.service_max_channel_pins);
+
+        let service_delete_attachments = service
+            .get("delete_attachments")
+            .map(|_| {
+                service
+                    .get_boolean("delete_attachments")
+                    .expect("Failed parsing 'service.delete_attachments' field")
+            })
+            .unwrap_or(defaults.service_delete_attachments);
+
+        let service_expiry_interval = service
+            .get("expiry_interval")
+            .map(|_| {
+                service
+                    .get_integer("expiry_interval")
+                    .expect("Failed parsing 'service.expiry_interval' field") as u64
+            })
+            .unwrap_or(defaults.service_expiry_interval);
+
+        let service_schedule_interval = service
+            .get("schedule_interval")
+            .map(|_| {
+                service
+                    .get_integer("schedule_interval")
+                    .expect("Failed parsing 'service.schedule_interval' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_schedule_interval);
+
+        let service_presence_timeout = service
+            .get("presence_timeout")
+            .map(|_| {
+                service
+                    .get_integer("presence_timeout")
+                    .expect("Failed parsing 'service.presence_timeout' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_presence_timeout);
+
+        let service_presence_idle = service
+            .get("presence_idle")
+            .map(|_| {
+                service
+                    .get_integer("presence_idle")
+                    .expect("Failed parsing 'service.presence_idle' field") as u64
+            })
+            .unwrap_or(defaults.service_presence_idle);
+
+        let service_webhook_interval = service
+            .get("webhook_interval")
+            .map(|_| {
+                service
+                    .get_integer("webhook_interval")
+                    .expect("Failed parsing 'service.webhook_interval' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_webhook_interval);
+
+        let service_webhook_timeout = service
+            .get("webhook_timeout")
+            .map(|_| {
+                service
+                    .get_integer("webhook_timeout")
+                    .expect("Failed parsing 'service.webhook_timeout' field") as u64
+            })
+            .unwrap_or(defaults.service_webhook_timeout);
+
+        let service_webhook_max_attempts = service
+            .get("webhook_max_attempts")
+            .map(|_| {
+                service
+                    .get_integer("webhook_max_attempts")
+                    .expect("Failed parsing 'service.webhook_max_attempts' field")
+                    as u32
+            })
+            .unwrap_or(defaults.service_webhook_max_attempts);
+
+        let service_webhook_rate_limit = service
+            .get("webhook_rate_limit")
+            .map(|_| {
+                service
+                    .get_integer("webhook_rate_limit")
+                    .expect("Failed parsing 'service.webhook_rate_limit' field")
+                    as u32
+            })
+            .unwrap_or(defaults.service_webhook_rate_limit);
+
+        let service_webhook_allowed_hosts = service
+            .get("webhook_allowed_hosts")
+            .map(|_| {
+                service
+                    .get_string("webhook_allowed_hosts")
+                    .expect("Failed parsing 'service.webhook_allowed_hosts' field")
+                    .to_string()
+            })
+            .unwrap_or(defaults.service_webhook_allowed_hosts);
+
+        let service_filter_interval = service
+            .get("filter_interval")
+            .map(|_| {
+                service
+                    .get_integer("filter_interval")
+                    .expect("Failed parsing 'service.filter_interval' field") as u64
+            })
+            .unwrap_or(defaults.service_filter_interval);
+
+        // Interval tasks panic or spin on a zero period
+        for (key, interval) in [
+            ("expiry_interval", service_expiry_interval),
+            ("schedule_interval", service_schedule_interval),
+            ("webhook_interval", service_webhook_interval),
+            ("filter_interval", service_filter_interval),
+        ] {
+            if interval == 0 {
+                panic!("'service.{key}' must be at least 1 second");
+            }
+        }
+
+        let service_upload_session_ttl = service
+            .get("upload_session_ttl")
+            .map(|_| {
+                service
+                    .get_integer("upload_session_ttl")
+                    .expect("Failed parsing 'service.upload_session_ttl' field")
+                    as u64
+            })
+            .unwrap_or(defaults.service_upload_session_ttl);
+
+        let service_gc_interval = service
+            .get("gc_interval")
+            .map(|_| {
+                service
+                    .get_integer("gc_interval")
+                    .expect("Failed parsing 'service.gc_interval' field") as u64
+            })
+            .unwrap_or(defaults.service_gc_interval);
+
+        let service_gc_grace_period = service
+            .get("gc_grace_period")
+            .map(|_| {
+                service
+                    .get_integer("gc_grace_period")
+                    .expect("Failed parsing 'service.gc_grace_period' field") as u64
+            })
+            .unwrap_or(defaults.service_gc_grace_period);
+
         let service_token_expiration = service
             .get_integer("token_expiration")
             .expect("Failed parsing 'service.token_expiration' field")
@@ -235,6 +667,43 @@ impl Config {
             service_allow_message_delete,
             service_allow_message_update,
             service_resource_dir,
+            service_staging_dir,
+            service_store,
+            service_max_resource_size,
+            service_user_quota,
+            service_supervisor_quota,
+            service_admin_quota,
+            service_channel_quota,
+            service_max_image_size,
+            service_max_image_dimension,
+            service_max_avatar_dimension,
+            service_avatar_allowed_types,
+            service_avatar_denied_types,
+            service_channel_allowed_types,
+            service_channel_denied_types,
+            service_builtin_allowed_types,
+            service_builtin_denied_types,
+            service_s3_endpoint,
+            service_s3_region,
+            service_s3_bucket,
+            service_s3_access_key,
+            service_s3_secret_key,
+            service_s3_allow_http,
+            service_max_channel_pins,
+            service_delete_attachments,
+            service_expiry_interval,
+            service_schedule_interval,
+            service_presence_timeout,
+            service_presence_idle,
+            service_webhook_interval,
+            service_webhook_timeout,
+            service_webhook_max_attempts,
+            service_webhook_rate_limit,
+            service_webhook_allowed_hosts,
+            service_filter_interval,
+            service_upload_session_ttl,
+            service_gc_interval,
+            service_gc_grace_period,
             service_token_expiration,
             net_address,
             net_rate_limit_replenish,
@@ -265,6 +734,13 @@ impl Config {
             .to_string()
     }
 
+    pub fn s3_secret_key(&self) -> String {
+        std::fs::read_to_string(&self.service_s3_secret_key)
+            .expect("Failed to read S3 secret key file")
+            .trim()
+            .to_string()
+    }
+
     pub fn write(&self) -> String {
         let Config {
             service_public_key,
@@ -273,6 +749,43 @@ impl Config {
             service_allow_message_delete,
             service_allow_message_update,
             service_resource_dir,
+            service_staging_dir,
+            service_store,
+            service_max_resource_size,
+            service_user_quota,
+            service_supervisor_quota,
+            service_admin_quota,
+            service_channel_quota,
+            service_max_image_size,
+            service_max_image_dimension,
+            service_max_avatar_dimension,
+            service_avatar_allowed_types,
+            service_avatar_denied_types,
+            service_channel_allowed_types,
+            service_channel_denied_types,
+            service_builtin_allowed_types,
+            service_builtin_denied_types,
+            service_s3_endpoint,
+            service_s3_region,
+            service_s3_bucket,
+            service_s3_access_key,
+            service_s3_secret_key,
+            service_s3_allow_http,
+            service_max_channel_pins,
+            service_delete_attachments,
+            service_expiry_interval,
+            service_schedule_interval,
+            service_presence_timeout,
+            service_presence_idle,
+            service_webhook_interval,
+            service_webhook_timeout,
+            service_webhook_max_attempts,
+            service_webhook_rate_limit,
+            service_webhook_allowed_hosts,
+            service_filter_interval,
+            service_upload_session_ttl,
+            service_gc_interval,
+            service_gc_grace_period,
             service_token_expiration,
             net_address,
             net_rate_limit_replenish,
@@ -311,6 +824,80 @@ allow_message_delete = {service_allow_message_delete}
 allow_message_update = {service_allow_message_update}
 # Directory where uploaded resources are stored.
 resource_dir = "{service_resource_dir}"
+# Directory for partially uploaded resources.
+staging_dir = "{service_staging_dir}"
+# Resource storage backend, either "local" or "s3".
+store = "{service_store}"
+# Maximum size of a single resource in bytes, at most 2147483647.
+max_resource_size = {service_max_resource_size}
+# Storage quota of users in bytes, 0 for no limit.
+user_quota = {service_user_quota}
+# Storage quota of supervisors in bytes, 0 for no limit.
+supervisor_quota = {service_supervisor_quota}
+# Storage quota of admins in bytes, 0 for no limit.
+admin_quota = {service_admin_quota}
+# Storage quota of channels in bytes, 0 for no limit.
+channel_quota = {service_channel_quota}
+# Maximum size in bytes of images which are decoded and processed.
+max_image_size = {service_max_image_size}
+# Maximum width and height of images in pixels.
+max_image_dimension = {service_max_image_dimension}
+# Maximum width and height of user avatars in pixels.
+max_avatar_dimension = {service_max_avatar_dimension}
+# Comma separated content types allowed in user namespaces, empty to allow all, e.g. "image/*".
+avatar_allowed_types = "{service_avatar_allowed_types}"
+# Comma separated content types rejected in user namespaces.
+avatar_denied_types = "{service_avatar_denied_types}"
+# Comma separated content types allowed in channel namespaces, empty to allow all.
+channel_allowed_types = "{service_channel_allowed_types}"
+# Comma separated content types rejected in channel namespaces.
+channel_denied_types = "{service_channel_denied_types}"
+# Comma separated content types allowed in the built-in namespace, empty to allow all.
+builtin_allowed_types = "{service_builtin_allowed_types}"
+# Comma separated content types rejected in the built-in namespace.
+builtin_denied_types = "{service_builtin_denied_types}"
+# Endpoint of the S3-compatible storage.
+s3_endpoint = "{service_s3_endpoint}"
+# Region of the S3 bucket.
+s3_region = "{service_s3_region}"
+# Bucket resources are stored in.
+s3_bucket = "{service_s3_bucket}"
+# Access key ID of the S3 credentials.
+s3_access_key = "{service_s3_access_key}"
+# Path to the file containing the secret access key of the S3 credentials.
+s3_secret_key = "{service_s3_secret_key}"
+# Allow unencrypted HTTP connections to the S3 endpoint.
+s3_allow_http = {service_s3_allow_http}
+# Maximum number of pinned messages per channel.
+max_channel_pins = {service_max_channel_pins}
+# Delete the attachments of a message when the message is deleted.
+delete_attachments = {service_delete_attachments}
+# Maximum interval in seconds between checks for expired messages.
+expiry_interval = {service_expiry_interval}
+# Maximum interval in seconds between checks for scheduled messages.
+schedule_interval = {service_schedule_interval}
+# Time in seconds without a heartbeat after which a user is offline.
+presence_timeout = {service_presence_timeout}
+# Time in seconds without activity after which a user is idle.
+presence_idle = {service_presence_idle}
+# Interval in seconds between webhook delivery runs.
+webhook_interval = {service_webhook_interval}
+# Timeout in seconds of a single webhook delivery attempt.
+webhook_timeout = {service_webhook_timeout}
+# Maximum number of attempts to deliver a webhook event.
+webhook_max_attempts = {service_webhook_max_attempts}
+# Maximum number of messages per minute an incoming webhook may post.
+webhook_rate_limit = {service_webhook_rate_limit}
+# Comma separated hosts which webhooks may target even if they resolve to loopback, private or link-local addresses.
+webhook_allowed_hosts = "{service_webhook_allowed_hosts}"
+# Interval in seconds between reloads of the content filter rules.
+filter_interval = {service_filter_interval}
+# Time in seconds after which abandoned upload sessions are removed.
+upload_session_ttl = {service_upload_session_ttl}
+# Interval in seconds between orphaned resource collections, 0 to only collect on demand.
+gc_interval = {service_gc_interval}
+# Time in seconds an orphaned resource or file is kept before it is collected.
+gc_grace_period = {service_gc_grace_period}
 # Token expiration time in hours.
 token_expiration = {service_token_expiration}
 
@@ -388,6 +975,58 @@ impl Default for Config {
                 "./resources"
             }
             .to_string(),
+            service_staging_dir: if cfg!(debug_assertions) {
+                "./dev/staging"
+            } else {
+                "./staging"
+            }
+            .to_string(),
+            service_store: "local".to_string(),
+            service_max_resource_size: 104857600,
+            service_user_quota: 1073741824,
+            service_supervisor_quota: 10737418240,
+            service_admin_quota: 0,
+            service_channel_quota: 10737418240,
+            service_max_image_size: 20971520,
+            service_max_image_dimension: 8192,
+            service_max_avatar_dimension: 1024,
+            service_avatar_allowed_types: "image/png".to_string(),
+            service_avatar_denied_types: String::new(),
+            service_channel_allowed_types: String::new(),
+            service_channel_denied_types: "application/vnd.microsoft.portable-executable,application/x-executable,application/x-mach-binary".to_string(),
+            service_builtin_allowed_types: String::new(),
+            service_builtin_denied_types: String::new(),
+            service_s3_endpoint: "http://127.0.0.1:9000".to_string(),
+            service_s3_region: "us-east-1".to_string(),
+            service_s3_bucket: "elysium".to_string(),
+            service_s3_access_key: String::new(),
+            service_s3_secret_key: if cfg!(debug_assertions) {
+                "./dev/s3-secret-key"
+            } else {
+                "./secure/s3-secret-key"
+            }
+            .to_string(),
+            service_s3_allow_http: false,
+            service_max_channel_pins: 50,
+            service_delete_attachments: false,
+            service_expiry_interval: 5,
+            service_schedule_interval: 5,
+            service_presence_timeout: 60,
+            service_presence_idle: 300,
+            service_webhook_interval: 5,
+            service_webhook_timeout: 10,
+            service_webhook_max_attempts: 8,
+            service_webhook_rate_limit: 30,
+            service_webhook_allowed_hosts: if cfg!(debug_assertions) {
+                "localhost,127.0.0.1,::1"
+            } else {
+                ""
+            }
+            .to_string(),
+            service_filter_interval: 30,
+            service_upload_session_ttl: 86400,
+            service_gc_interval: 3600,
+            service_gc_grace_period: 86400,
             service_token_expiration: 168,
             net_address: "127.0.0.1:50051".to_string(),
             net_rate_limit_replenish: 100,
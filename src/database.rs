@@ -1,8 +1,14 @@
-use crate::config;
+use crate::error::Error;
+use crate::{config, utils};
+use elysium_rust::Timestamp;
 use std::ops::Deref;
 use surrealdb::Surreal;
 use surrealdb::engine::remote::ws::{Client, Ws};
 use surrealdb::opt::auth::Root;
+use surrealdb::types::SurrealValue;
+
+/// Migration storing the mentions of messages sent before they were stored with the message.
+const MIGRATION_MESSAGE_MENTIONS: &str = "message_mentions";
 
 #[derive(Clone, Debug)]
 pub struct Database {
@@ -44,12 +50,101 @@ impl Database {
 DEFINE TABLE IF NOT EXISTS user SCHEMALESS;
 DEFINE TABLE IF NOT EXISTS channel SCHEMALESS;
 DEFINE TABLE IF NOT EXISTS message SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS message_revision SCHEMALESS;
 DEFINE TABLE IF NOT EXISTS resource SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS read_state SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS pin SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS channel_settings SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS scheduled_message SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS webhook SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS webhook_delivery SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS incoming_webhook SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS bot SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS report SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS sanction SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS filter_rule SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS invite SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS upload_session SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS blob SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS builtin_blob SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS storage_quota SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS storage_usage SCHEMALESS;
+DEFINE TABLE IF NOT EXISTS migration SCHEMALESS;
+
+DEFINE FIELD IF NOT EXISTS slow_mode ON channel_settings TYPE int DEFAULT 0;
+
+DEFINE INDEX IF NOT EXISTS message_channel ON message FIELDS channel_id, content.created_at.millis;
+DEFINE INDEX IF NOT EXISTS message_revision_message ON message_revision FIELDS message_id, replaced_at.millis;
+DEFINE INDEX IF NOT EXISTS read_state_channel ON read_state FIELDS channel_id, user_id;
+DEFINE INDEX IF NOT EXISTS message_expires_at ON message FIELDS expires_at;
+DEFINE INDEX IF NOT EXISTS pin_channel ON pin FIELDS channel_id;
+DEFINE INDEX IF NOT EXISTS scheduled_message_send_at ON scheduled_message FIELDS send_at.millis;
+DEFINE INDEX IF NOT EXISTS scheduled_message_user ON scheduled_message FIELDS user_id;
+DEFINE INDEX IF NOT EXISTS webhook_channel ON webhook FIELDS channel_id;
+DEFINE INDEX IF NOT EXISTS webhook_delivery_due ON webhook_delivery FIELDS status, next_attempt.millis;
+DEFINE INDEX IF NOT EXISTS webhook_delivery_webhook ON webhook_delivery FIELDS webhook_id;
+DEFINE INDEX IF NOT EXISTS incoming_webhook_channel ON incoming_webhook FIELDS channel_id;
+DEFINE INDEX IF NOT EXISTS report_status ON report FIELDS status, created_at.millis;
+DEFINE INDEX IF NOT EXISTS sanction_user ON sanction FIELDS user_id, channel_id;
+DEFINE INDEX IF NOT EXISTS filter_rule_channel ON filter_rule FIELDS channel_id;
+DEFINE INDEX IF NOT EXISTS invite_channel ON invite FIELDS channel_id;
+DEFINE INDEX IF NOT EXISTS upload_session_updated_at ON upload_session FIELDS updated_at.millis;
+DEFINE INDEX IF NOT EXISTS blob_refs ON blob FIELDS refs, touched_at.millis;
+DEFINE INDEX IF NOT EXISTS resource_namespace ON resource FIELDS resource_id.namespace, resource_id.key;
+
+UPDATE resource SET digest = '' WHERE digest IS NONE;
+UPDATE resource SET content_type = 'application/octet-stream' WHERE content_type IS NONE;
+
+IF array::len(SELECT * FROM storage_usage LIMIT 1) = 0 {
+    FOR $row IN (SELECT user_id, math::sum(meta.size) AS used FROM resource GROUP BY user_id) {
+        UPSERT type::record('storage_usage', 'user:' + $row.user_id) SET used = $row.used;
+    };
+    FOR $row IN (SELECT resource_id.namespace AS namespace, math::sum(meta.size) AS used FROM resource GROUP BY namespace) {
+        UPSERT type::record('storage_usage', 'namespace:' + $row.namespace) SET used = $row.used;
+    };
+};
 "#,
         )
         .await
         .expect("Failed to setup user table");
+
+        self.migrate(
+            MIGRATION_MESSAGE_MENTIONS,
+            crate::chat::backfill_mentions(self),
+        )
+        .await
+        .expect("Failed to backfill message mentions");
     }
+
+    /// Runs a data migration unless it already completed, recording its completion.
+    async fn migrate(
+        &self,
+        name: &str,
+        migration: impl Future<Output = Result<(), Error>>,
+    ) -> Result<(), Error> {
+        let done: Option<Migration> = self.select(("migration", name)).await?;
+
+        if done.is_some() {
+            return Ok(());
+        }
+
+        migration.await?;
+
+        let _: Option<Migration> = self
+            .upsert(("migration", name))
+            .content(Migration {
+                completed_at: utils::get_timestamp(),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Marker of a completed data migration.
+#[derive(Clone, Debug, SurrealValue)]
+struct Migration {
+    completed_at: Timestamp,
 }
 
 impl Deref for Database {
@@ -1,23 +1,77 @@
 use elysium_rust::common::v1::ErrorCode;
 use std::fmt::{Debug, Display, Formatter};
+use std::time::Duration;
+use tonic::Response;
+use tonic::metadata::MetadataValue;
+
+/// Response metadata telling rate limited clients how many milliseconds to wait.
+pub const RETRY_AFTER_METADATA_KEY: &str = "retry-after-ms";
 
 #[derive(Debug)]
-pub struct Error(elysium_rust::common::v1::Error);
+pub struct Error {
+    inner: elysium_rust::common::v1::Error,
+    retry_after: Option<Duration>,
+}
 
 impl Error {
     pub fn new(code: ErrorCode, message: impl ToString) -> Self {
-        Self(elysium_rust::common::v1::Error {
-            code: code as i32,
-            message: message.to_string(),
-        })
+        Self {
+            inner: elysium_rust::common::v1::Error {
+                code: code as i32,
+                message: message.to_string(),
+            },
+            retry_after: None,
+        }
     }
 
     pub fn invalid_argument() -> Self {
         ErrorCode::InvalidFormat.into()
     }
 
+    /// Error telling the client to retry after the given duration, see [`respond`].
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..Self::new(
+                ErrorCode::RateLimited,
+                format!(
+                    "Rate limit exceeded, retry in {} ms",
+                    retry_after.as_millis()
+                ),
+            )
+        }
+    }
+
     pub fn code(&self) -> ErrorCode {
-        ErrorCode::try_from(self.0.code).unwrap_or(ErrorCode::Internal)
+        ErrorCode::try_from(self.inner.code).unwrap_or(ErrorCode::Internal)
+    }
+
+    /// Time after which a rate limited request may succeed.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+/// Builds the response of a call, falling back to `on_error` for failed calls.
+///
+/// The wait time of rate limited calls is also put into the [`RETRY_AFTER_METADATA_KEY`]
+/// metadata, since error messages are not meant to be parsed.
+pub fn respond<T>(result: Result<T, Error>, on_error: impl FnOnce(Error) -> T) -> Response<T> {
+    match result {
+        Ok(message) => Response::new(message),
+        Err(err) => {
+            let retry_after = err.retry_after();
+            let mut response = Response::new(on_error(err));
+
+            if let Some(retry_after) = retry_after {
+                response.metadata_mut().insert(
+                    RETRY_AFTER_METADATA_KEY,
+                    MetadataValue::from(retry_after.as_millis() as u64),
+                );
+            }
+
+            response
+        }
     }
 }
 
@@ -26,10 +80,10 @@ impl Display for Error {
         write!(
             f,
             "{}: {}",
-            ErrorCode::try_from(self.0.code)
+            ErrorCode::try_from(self.inner.code)
                 .map(|code| code.as_str_name())
                 .unwrap_or("INVALID_ERROR_CODE"),
-            self.0.message
+            self.inner.message
         )
     }
 }
@@ -47,6 +101,7 @@ impl From<ErrorCode> for Error {
                 ErrorCode::NotFound => "The requested item could not be found",
                 ErrorCode::AlreadyExists => "The requested item already exists",
                 ErrorCode::InvalidFormat => "An invalid message was given",
+                ErrorCode::RateLimited => "Rate limit exceeded",
             },
         )
     }
@@ -54,13 +109,16 @@ impl From<ErrorCode> for Error {
 
 impl From<elysium_rust::common::v1::Error> for Error {
     fn from(value: elysium_rust::common::v1::Error) -> Self {
-        Self(value)
+        Self {
+            inner: value,
+            retry_after: None,
+        }
     }
 }
 
 impl Into<elysium_rust::common::v1::Error> for Error {
     fn into(self) -> elysium_rust::common::v1::Error {
-        self.0
+        self.inner
     }
 }
 
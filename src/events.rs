@@ -0,0 +1,92 @@
+use crate::chat::Pin;
+use elysium_rust::Message;
+use elysium_rust::chat::v1::ChannelPermission;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+/// Maximum number of events buffered per channel before slow subscribers start lagging.
+pub const EVENT_BUFFER_SIZE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum ChannelEvent {
+    MessageCreated(Message),
+    MessageUpdated(Message),
+    MessageDeleted {
+        channel_id: String,
+        message_id: String,
+    },
+    MessagePinned(Pin),
+    MessageUnpinned {
+        channel_id: String,
+        message_id: String,
+    },
+    /// A user joined the channel or their permission changed.
+    MemberChanged {
+        channel_id: String,
+        user_id: String,
+        permission: ChannelPermission,
+    },
+    /// A message only delivered to a single user and never stored.
+    Ephemeral {
+        recipient: String,
+        message: Message,
+    },
+}
+
+impl ChannelEvent {
+    pub fn channel_id(&self) -> &str {
+        match self {
+            ChannelEvent::MessageCreated(message)
+            | ChannelEvent::MessageUpdated(message)
+            | ChannelEvent::Ephemeral { message, .. } => &message.channel_id,
+            ChannelEvent::MessagePinned(pin) => &pin.channel_id,
+            ChannelEvent::MessageDeleted { channel_id, .. }
+            | ChannelEvent::MessageUnpinned { channel_id, .. }
+            | ChannelEvent::MemberChanged { channel_id, .. } => channel_id,
+        }
+    }
+}
+
+/// In-memory fan-out of channel events to subscribed clients.
+#[derive(Clone, Debug)]
+pub struct Events {
+    channels: Arc<RwLock<HashMap<String, broadcast::Sender<ChannelEvent>>>>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self {
+            channels: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn subscribe(&self, channel_id: &str) -> broadcast::Receiver<ChannelEvent> {
+        let mut channels = self.channels.write().expect("Event channels poisoned");
+
+        channels
+            .entry(channel_id.to_string())
+            .or_insert_with(|| broadcast::channel(EVENT_BUFFER_SIZE).0)
+            .subscribe()
+    }
+
+    /// Drops the senders of channels nobody is subscribed to anymore.
+    pub fn sweep(&self) {
+        self.channels
+            .write()
+            .expect("Event channels poisoned")
+            .retain(|_, sender| sender.receiver_count() > 0);
+    }
+
+    pub fn publish(&self, event: ChannelEvent) {
+        let mut channels = self.channels.write().expect("Event channels poisoned");
+        let channel_id = event.channel_id().to_string();
+
+        if let Some(sender) = channels.get(&channel_id)
+            && sender.send(event).is_err()
+        {
+            // Nobody is listening anymore
+            channels.remove(&channel_id);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::chat::{self, Cursor, Direction, MessageRevision};
+use crate::database::Database;
+use crate::error::Error;
+use crate::resource::{self, ResourceDescriptor};
+use crate::utils;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Content, Message, ResourceId, ResourceMeta};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Number of messages read from the database and written as one chunk.
+const EXPORT_BATCH_SIZE: u32 = 500;
+
+/// Number of chunks buffered between reading messages and writing the file.
+const EXPORT_BUFFER_SIZE: usize = 4;
+
+/// Content type of channel exports.
+pub const EXPORT_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Writes the whole history of a channel as JSON Lines into a resource in the channel's
+/// namespace.
+///
+/// The first line describes the channel, every following line is one message, oldest first,
+/// along with the earlier revisions of its content. Reactions are not exported, since the
+/// server does not store any.
+/// `progress` is called with the number of exported messages after every batch.
+pub async fn export_channel(
+    database: &Database,
+    channel_id: &str,
+    user_id: &str,
+    progress: impl Fn(u64),
+) -> Result<ResourceDescriptor, Error> {
+    let channel = chat::get_channel(database, channel_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+    let exported_at = utils::get_timestamp();
+    let resource_id = ResourceId {
+        namespace: channel.channel_id.clone(),
+        key: format!("export-{}.jsonl", exported_at.millis),
+    };
+
+    let pinned = chat::get_pinned_messages(database, channel.channel_id.clone())
+        .await?
+        .into_iter()
+        .map(|message| message.message_id)
+        .collect::<HashSet<_>>();
+
+    let (tx, rx) = mpsc::channel(EXPORT_BUFFER_SIZE);
+
+    let writer = resource::write(database, None, ReceiverStream::new(rx));
+
+    let producer = async move {
+        let header = json!({
+            "type": "channel",
+            "channel_id": channel.channel_id,
+            "name": channel.name,
+            "description": channel.description,
+            "members": channel.members,
+            "exported_at": exported_at.millis,
+            "exported_by": user_id,
+        });
+
+        let mut count = 0;
+        let mut chunk = format!("{header}\n").into_bytes();
+        let mut cursor = Some(Cursor::start());
+
+        while let Some(current) = cursor {
+            let (messages, next) = chat::read_messages(
+                database,
+                channel.channel_id.clone(),
+                EXPORT_BATCH_SIZE,
+                current,
+                Direction::Forward,
+            )
+            .await?;
+
+            let ids = messages.iter().map(|message| message.message_id.clone());
+            let mut revisions = HashMap::<_, Vec<_>>::new();
+
+            for revision in chat::get_revisions(database, ids.collect()).await? {
+                revisions
+                    .entry(revision.message_id.clone())
+                    .or_default()
+                    .push(revision);
+            }
+
+            for message in &messages {
+                let line = message_line(
+                    message,
+                    pinned.contains(&message.message_id),
+                    revisions
+                        .get(&message.message_id)
+                        .map_or(&[], Vec::as_slice),
+                );
+                chunk.extend_from_slice(format!("{line}\n").as_bytes());
+            }
+
+            count += messages.len() as u64;
+
+            // The writer only hangs up after failing, it reports the error itself
+            if tx.send(Ok(std::mem::take(&mut chunk))).await.is_err() {
+                break;
+            }
+
+            progress(count);
+            cursor = next;
+        }
+
+        Ok::<_, Error>(())
+    };
+
+    // A blob written for a failed export is never referenced and swept later
+    let (written, produced) = tokio::join!(writer, producer);
+    let mut written = written?;
+    produced?;
+
+    // The server wrote the export itself, so its content type can be trusted
+    written.content_type = EXPORT_CONTENT_TYPE.to_string();
+
+    resource::create(
+        database,
+        ResourceDescriptor::written(
+            resource_id,
+            ResourceMeta {
+                size: 0,
+                timestamp: exported_at,
+                metadata: Default::default(),
+            },
+            user_id.to_string(),
+            written,
+        )?,
+    )
+    .await
+}
+
+fn message_line(
+    message: &Message,
+    pinned: bool,
+    revisions: &[MessageRevision],
+) -> serde_json::Value {
+    json!({
+        "type": "message",
+        "message_id": message.message_id,
+        "user_id": message.user_id,
+        "text": message.content.text,
+        "attachments": attachments(&message.content),
+        "created_at": message.content.created_at.millis,
+        "pinned": pinned,
+        "revisions": revisions
+            .iter()
+            .map(|revision| json!({
+                "text": revision.content.text,
+                "attachments": attachments(&revision.content),
+                "replaced_at": revision.replaced_at.millis,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn attachments(content: &Content) -> Vec<serde_json::Value> {
+    content
+        .attachments
+        .iter()
+        .map(|id| json!({ "namespace": id.namespace, "key": id.key }))
+        .collect()
+}
@@ -0,0 +1,339 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::moderation::{self, Report};
+use crate::utils;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Content, Message, Timestamp};
+use regex::{Regex, RegexBuilder};
+use std::sync::{Arc, RwLock};
+use surrealdb::types::SurrealValue;
+
+pub const ID_LENGTH: usize = 12;
+
+pub const KIND_WORD: &str = "word";
+pub const KIND_REGEX: &str = "regex";
+pub const KIND_DOMAIN: &str = "domain";
+
+pub const ACTION_REJECT: &str = "reject";
+pub const ACTION_MASK: &str = "mask";
+pub const ACTION_FLAG: &str = "flag";
+
+/// Maximum size of a compiled rule, keeps user supplied regexes from exhausting memory.
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+
+/// Character replacing every character of masked matches.
+const MASK_CHAR: char = '*';
+
+pub fn build_rule_id() -> String {
+    nanoid::nanoid!(ID_LENGTH)
+}
+
+pub async fn create_rule(database: &Database, rule: FilterRule) -> Result<FilterRule, Error> {
+    let rule: Option<FilterRule> = database
+        .create(("filter_rule", rule.rule_id.as_str()))
+        .content(rule)
+        .await?;
+
+    rule.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to create filter rule",
+    ))
+}
+
+pub async fn get_rule(database: &Database, rule_id: &str) -> Result<Option<FilterRule>, Error> {
+    let rule: Option<FilterRule> = database.select(("filter_rule", rule_id)).await?;
+
+    Ok(rule)
+}
+
+pub async fn delete_rule(database: &Database, rule_id: &str) -> Result<(), Error> {
+    let _: Option<FilterRule> = database.delete(("filter_rule", rule_id)).await?;
+
+    Ok(())
+}
+
+pub async fn get_rules(database: &Database) -> Result<Vec<FilterRule>, Error> {
+    let rules: Vec<FilterRule> = database
+        .query("SELECT * FROM filter_rule ORDER BY created_at.millis ASC;")
+        .await?
+        .take(0)?;
+
+    Ok(rules)
+}
+
+/// Returns the rules of a channel, or the server-wide rules if `channel_id` is empty.
+pub async fn get_channel_rules(
+    database: &Database,
+    channel_id: &str,
+) -> Result<Vec<FilterRule>, Error> {
+    let rules: Vec<FilterRule> = database
+        .query(
+            r#"
+SELECT *
+FROM filter_rule
+WHERE channel_id = $channel
+ORDER BY created_at.millis ASC;
+"#,
+        )
+        .bind(("channel", channel_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(rules)
+}
+
+/// Compiles a rule pattern into a case-insensitive regex.
+///
+/// Words only match whole words and domains also match their subdomains.
+/// Word boundaries are only required at word characters, so words like `c++` or `@here`
+/// match as well.
+pub fn compile(kind: &str, pattern: &str) -> Result<Regex, Error> {
+    if pattern.is_empty() {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Filter pattern must not be empty",
+        ));
+    }
+
+    let source = match kind {
+        KIND_WORD => {
+            let boundary = |c: Option<char>| match c {
+                Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+                _ => "",
+            };
+
+            format!(
+                "{}{}{}",
+                boundary(pattern.chars().next()),
+                regex::escape(pattern),
+                boundary(pattern.chars().next_back())
+            )
+        }
+        KIND_REGEX => pattern.to_string(),
+        KIND_DOMAIN => format!(
+            r"\b(?:[a-z0-9-]+\.)*{}\b",
+            regex::escape(pattern.trim_start_matches("*."))
+        ),
+        _ => {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                "Unknown filter rule kind",
+            ));
+        }
+    };
+
+    RegexBuilder::new(&source)
+        .case_insensitive(true)
+        .size_limit(MAX_COMPILED_SIZE)
+        .build()
+        .map_err(|err| {
+            Error::new(
+                ErrorCode::InvalidFormat,
+                format!("Invalid filter pattern: {err}"),
+            )
+        })
+}
+
+/// Masks all matches of `regex` in `text`.
+pub fn mask(regex: &Regex, text: &str) -> String {
+    regex
+        .replace_all(text, |caps: &regex::Captures| {
+            MASK_CHAR.to_string().repeat(caps[0].chars().count())
+        })
+        .into_owned()
+}
+
+/// Reports a message which matched flagging rules for review by a supervisor.
+pub async fn flag(
+    database: &Database,
+    message: &Message,
+    rule_ids: &[String],
+) -> Result<(), Error> {
+    if rule_ids.is_empty() {
+        return Ok(());
+    }
+
+    moderation::create_report(
+        database,
+        Report {
+            report_id: moderation::build_id(),
+            message_id: message.message_id.clone(),
+            channel_id: message.channel_id.clone(),
+            // Reports by the content filter have no reporter
+            reporter_id: String::new(),
+            user_id: message.user_id.clone(),
+            content: message.content.clone(),
+            reason: format!("Matched content filter rules: {}", rule_ids.join(", ")),
+            status: moderation::REPORT_OPEN.to_string(),
+            reviewer_id: String::new(),
+            resolution: String::new(),
+            created_at: utils::get_timestamp(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// A content filter rule, server-wide if `channel_id` is empty.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct FilterRule {
+    pub rule_id: String,
+    pub channel_id: String,
+    pub kind: String,
+    pub pattern: String,
+    pub action: String,
+    pub created_by: String,
+    pub created_at: Timestamp,
+}
+
+#[derive(Clone, Debug)]
+struct CompiledRule {
+    rule_id: String,
+    channel_id: String,
+    action: String,
+    regex: Regex,
+}
+
+/// The compiled filter rules of all channels, swapped out on every reload.
+#[derive(Clone, Debug)]
+pub struct Filters {
+    rules: Arc<RwLock<Arc<Vec<CompiledRule>>>>,
+}
+
+impl Filters {
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(Arc::new(Vec::new()))),
+        }
+    }
+
+    /// Loads and compiles all rules from the database.
+    ///
+    /// Rules which fail to compile are skipped, so one bad rule does not disable filtering.
+    pub async fn reload(&self, database: &Database) -> Result<(), Error> {
+        let rules = get_rules(database)
+            .await?
+            .into_iter()
+            .filter_map(|rule| match compile(&rule.kind, &rule.pattern) {
+                Ok(regex) => Some(CompiledRule {
+                    rule_id: rule.rule_id,
+                    channel_id: rule.channel_id,
+                    action: rule.action,
+                    regex,
+                }),
+                Err(err) => {
+                    tracing::warn!("Skipping filter rule '{}': {err}", rule.rule_id);
+                    None
+                }
+            })
+            .collect();
+
+        *self.rules.write().expect("Filter rules poisoned") = Arc::new(rules);
+
+        Ok(())
+    }
+
+    /// Runs the server-wide and channel rules over the content text.
+    ///
+    /// Matches of masking rules are masked in place. Returns the IDs of matching flagging rules,
+    /// or an error if a rejecting rule matched.
+    pub fn apply(&self, channel_id: &str, content: &mut Content) -> Result<Vec<String>, Error> {
+        let rules = self.rules.read().expect("Filter rules poisoned").clone();
+        let mut flagged = Vec::new();
+        let mut text = content.text.clone();
+
+        for rule in rules
+            .iter()
+            .filter(|rule| rule.channel_id.is_empty() || rule.channel_id == channel_id)
+        {
+            if !rule.regex.is_match(&content.text) {
+                continue;
+            }
+
+            match rule.action.as_str() {
+                ACTION_REJECT => {
+                    return Err(Error::new(
+                        ErrorCode::InvalidFormat,
+                        "Message was rejected by the content filter",
+                    ));
+                }
+                ACTION_MASK => text = mask(&rule.regex, &text),
+                ACTION_FLAG => flagged.push(rule.rule_id.clone()),
+                _ => {}
+            }
+        }
+
+        content.text = text;
+
+        Ok(flagged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_matches_whole_words() {
+        let regex = compile(KIND_WORD, "bad").unwrap();
+
+        assert!(regex.is_match("that is BAD!"));
+        assert!(!regex.is_match("a badge"));
+    }
+
+    #[test]
+    fn word_matches_symbols_at_edges() {
+        let regex = compile(KIND_WORD, "c++").unwrap();
+
+        assert!(regex.is_match("I like C++."));
+        assert!(!regex.is_match("abc++"));
+
+        let regex = compile(KIND_WORD, "@here").unwrap();
+
+        assert!(regex.is_match("hey @here!"));
+        assert!(regex.is_match("hey,@here"));
+        assert!(!regex.is_match("hey @heresy"));
+    }
+
+    #[test]
+    fn word_is_literal() {
+        let regex = compile(KIND_WORD, "a.b").unwrap();
+
+        assert!(regex.is_match("say a.b now"));
+        assert!(!regex.is_match("say axb now"));
+    }
+
+    #[test]
+    fn domain_matches_subdomains() {
+        let regex = compile(KIND_DOMAIN, "*.example.com").unwrap();
+
+        assert!(regex.is_match("see https://example.com/page"));
+        assert!(regex.is_match("see cdn.EXAMPLE.com"));
+        assert!(!regex.is_match("see notexample.com"));
+        assert!(!regex.is_match("see example.community"));
+    }
+
+    #[test]
+    fn compile_rejects_invalid_rules() {
+        for (kind, pattern) in [
+            (KIND_WORD, ""),
+            ("unknown", "bad"),
+            (KIND_REGEX, "(unclosed"),
+            (KIND_REGEX, r"(?:\w{100}){100}"),
+        ] {
+            assert_eq!(
+                compile(kind, pattern).unwrap_err().code(),
+                ErrorCode::InvalidFormat,
+                "{kind} {pattern}"
+            );
+        }
+    }
+
+    #[test]
+    fn mask_replaces_every_character() {
+        let regex = compile(KIND_REGEX, "b[aä]d").unwrap();
+
+        assert_eq!(mask(&regex, "bäd, BAD and fine"), "***, *** and fine");
+    }
+}
@@ -0,0 +1,231 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::resource::{self, Blob, ResourceDescriptor};
+use crate::{chat, config, store, user, utils};
+use elysium_rust::ResourceId;
+use elysium_rust::common::v1::ErrorCode;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use surrealdb::types::SurrealValue;
+use tokio::fs;
+
+/// Finds resources and files nothing refers to anymore and removes them, unless `dry_run` is set.
+///
+/// Orphaned are resources in namespaces whose user or channel was deleted, files below the
+/// resource directory without a resource or blob record, and leftovers of interrupted writes in
+/// the staging directory. Everything younger than the configured grace period is kept.
+pub async fn collect(database: &Database, dry_run: bool) -> Result<GcReport, Error> {
+    let config = config::get();
+    let grace = Duration::from_secs(config.service_gc_grace_period);
+
+    let mut report = GcReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let before = utils::get_timestamp()
+        .millis
+        .saturating_sub(grace.as_millis() as u64);
+
+    for desc in get_orphaned_resources(database, before).await? {
+        if !dry_run {
+            resource::remove(database, &desc.resource_id).await?;
+        }
+
+        report.bytes += desc.meta.size.max(0) as u64;
+        report.resources.push(desc.resource_id);
+    }
+
+    let cutoff = SystemTime::now() - grace;
+    let resource_dir = Path::new(&config.service_resource_dir);
+
+    for (namespace, path) in list_dirs(resource_dir).await? {
+        // Built-in resources are shipped as files and blobs have their own records
+        if namespace.starts_with('.') || namespace == resource::BUILTIN_NAMESPACE {
+            continue;
+        }
+
+        for (key, file) in list_files(&path, cutoff).await? {
+            let resource_id = ResourceId {
+                namespace: namespace.clone(),
+                key,
+            };
+
+            // In a dry run, collected resources still exist but their files are already reported
+            if !report.resources.contains(&resource_id)
+                && !resource::exists(database, &resource_id).await?
+            {
+                collect_file(&mut report, file, dry_run).await?;
+            }
+        }
+    }
+
+    // Blobs of other stores are not on this disk
+    if config.service_store == store::STORE_LOCAL {
+        for (_, path) in list_dirs(&resource_dir.join(store::LOCAL_BLOB_DIR)).await? {
+            for (digest, file) in list_files(&path, cutoff).await? {
+                let blob: Option<Blob> = database.select(("blob", digest.as_str())).await?;
+
+                if blob.is_none() {
+                    collect_file(&mut report, file, dry_run).await?;
+                }
+            }
+        }
+    }
+
+    // Staged uploads are removed with their session, only interrupted writes are left over
+    for (name, file) in list_files(Path::new(&config.service_staging_dir), cutoff).await? {
+        if name.starts_with('~') {
+            collect_file(&mut report, file, dry_run).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns resources older than `before` whose namespace belongs to no user or channel.
+async fn get_orphaned_resources(
+    database: &Database,
+    before: u64,
+) -> Result<Vec<ResourceDescriptor>, Error> {
+    let namespaces: Vec<NamespaceRow> = database
+        .query("SELECT resource_id.namespace AS namespace FROM resource GROUP BY namespace;")
+        .await?
+        .take(0)?;
+
+    let mut orphaned = Vec::new();
+
+    for NamespaceRow { namespace } in namespaces {
+        let alive = if namespace == resource::BUILTIN_NAMESPACE {
+            true
+        } else if let Some(user_id) = namespace.strip_prefix("user.") {
+            user::exists(database, user_id).await?
+        } else {
+            chat::channel_exists(database, &namespace).await?
+        };
+
+        if alive {
+            continue;
+        }
+
+        let resources: Vec<ResourceDescriptor> = database
+            .query(
+                r#"
+SELECT *
+FROM resource
+WHERE resource_id.namespace = $namespace AND meta.timestamp.millis < $before;
+"#,
+            )
+            .bind(("namespace", namespace))
+            .bind(("before", before))
+            .await?
+            .take(0)?;
+
+        orphaned.extend(resources);
+    }
+
+    Ok(orphaned)
+}
+
+async fn collect_file(
+    report: &mut GcReport,
+    file: OrphanedFile,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if !dry_run
+        && let Err(e) = fs::remove_file(&file.path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::error!("Failed removing orphaned file: {e}");
+        return Err(Error::new(
+            ErrorCode::Internal,
+            "Failed to remove orphaned file",
+        ));
+    }
+
+    report.bytes += file.size;
+    report.files.push(file.path.display().to_string());
+
+    Ok(())
+}
+
+/// Lists the subdirectories of a directory by name, nothing if the directory does not exist.
+async fn list_dirs(dir: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut dirs = Vec::new();
+
+    for (name, path, meta) in read_dir(dir).await? {
+        if meta.is_dir() {
+            dirs.push((name, path));
+        }
+    }
+
+    Ok(dirs)
+}
+
+/// Lists the files of a directory by name which were last modified before `cutoff`.
+async fn list_files(dir: &Path, cutoff: SystemTime) -> Result<Vec<(String, OrphanedFile)>, Error> {
+    let mut files = Vec::new();
+
+    for (name, path, meta) in read_dir(dir).await? {
+        // Files without a modification time are kept, their age is unknown
+        if meta.is_file() && meta.modified().is_ok_and(|modified| modified < cutoff) {
+            files.push((
+                name,
+                OrphanedFile {
+                    path,
+                    size: meta.len(),
+                },
+            ));
+        }
+    }
+
+    Ok(files)
+}
+
+async fn read_dir(dir: &Path) -> Result<Vec<(String, PathBuf, std::fs::Metadata)>, Error> {
+    let map_err = |e: std::io::Error| {
+        tracing::error!("Failed reading directory for garbage collection: {e}");
+        Error::new(ErrorCode::Internal, "Failed to collect orphaned resources")
+    };
+
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(map_err(e)),
+    };
+
+    let mut result = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await.map_err(map_err)? {
+        // Names which are not valid UTF-8 were never written by the server
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        let meta = entry.metadata().await.map_err(map_err)?;
+        result.push((name, entry.path(), meta));
+    }
+
+    Ok(result)
+}
+
+#[derive(Clone, Debug, SurrealValue)]
+struct NamespaceRow {
+    namespace: String,
+}
+
+struct OrphanedFile {
+    path: PathBuf,
+    size: u64,
+}
+
+/// What a garbage collection removed, or would have removed in a dry run.
+#[derive(Clone, Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub resources: Vec<ResourceId>,
+    /// Paths of the removed files.
+    pub files: Vec<String>,
+    /// Total size of the removed resources and files in bytes.
+    pub bytes: u64,
+}
@@ -1,9 +1,12 @@
 use crate::connect_info::ConnectInfoInterceptor;
-use crate::services::{ChatService, GeneralService, ResourceService, UserService};
+use crate::services::{
+    ChatService, GeneralService, ModerationService, ResourceService, UserService,
+};
 use crate::state::ServerState;
 use crate::utils::{COMPRESSION, MAX_MESSAGE_SIZE};
 use elysium_rust::chat::v1::chat_service_server::ChatServiceServer;
 use elysium_rust::general::v1::general_service_server::GeneralServiceServer;
+use elysium_rust::moderation::v1::moderation_service_server::ModerationServiceServer;
 use elysium_rust::resource::v1::resource_service_server::ResourceServiceServer;
 use elysium_rust::user::v1::user_service_server::UserServiceServer;
 use std::net::SocketAddr;
@@ -16,17 +19,31 @@ use tower_governor::governor::GovernorConfigBuilder;
 use tower_governor::key_extractor::SmartIpKeyExtractor;
 
 mod auth;
+mod bot;
 mod chat;
 mod config;
 mod connect_info;
 mod database;
 mod error;
+mod events;
+mod export;
+mod filter;
+mod gc;
+mod media;
+mod moderation;
+mod presence;
+mod quota;
+mod ratelimit;
 mod resource;
 mod services;
 mod state;
+mod store;
+mod tasks;
 mod trace;
+mod upload;
 mod user;
 mod utils;
+mod webhook;
 
 fn main() {
     println!("Loading configuration...");
@@ -56,6 +73,9 @@ fn main() {
             tracing::info!("Initializing authentication...");
             auth::init().await;
 
+            tracing::info!("Initializing resource store...");
+            store::init();
+
             tokio::select! {
                 _ = serve() => (),
                 _ = exit_signal() => (),
@@ -71,6 +91,9 @@ async fn serve() {
     tracing::info!("Initializing Server State...");
     let state = ServerState::new().await;
 
+    tracing::info!("Spawning background tasks...");
+    tasks::spawn(&state);
+
     // Create initial admin if not present
     user::create_admin(state.database())
         .await
@@ -122,6 +145,13 @@ async fn serve() {
                 .send_compressed(COMPRESSION)
                 .max_decoding_message_size(MAX_MESSAGE_SIZE)
                 .max_encoding_message_size(MAX_MESSAGE_SIZE),
+        )
+        .add_service(
+            ModerationServiceServer::new(ModerationService::new(state.clone()))
+                .accept_compressed(COMPRESSION)
+                .send_compressed(COMPRESSION)
+                .max_decoding_message_size(MAX_MESSAGE_SIZE)
+                .max_encoding_message_size(MAX_MESSAGE_SIZE),
         );
 
     builder
@@ -0,0 +1,360 @@
+use crate::config;
+use crate::database::Database;
+use crate::error::Error;
+use crate::resource::{self, ResourceDescriptor, Written};
+use crate::utils::VecStream;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{ResourceId, ResourceMeta};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+use tonic::codegen::tokio_stream::StreamExt;
+
+/// Edge lengths in pixels of the thumbnails generated for every image.
+pub const THUMBNAIL_SIZES: [u32; 3] = [32, 64, 256];
+
+/// Formats accepted for images, avatars have to be PNG.
+const IMAGE_FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+/// Quality from 1 to 100 that JPEG images are re-encoded with.
+const JPEG_QUALITY: u8 = 85;
+
+/// Saves an uploaded resource, decoding and processing it first if it is an image.
+///
+/// Images are re-encoded without metadata and their thumbnails are saved as derived resources
+/// next to them. Avatars have to pass all checks, other images which cannot be processed, e.g.
+/// GIFs, animations or images over the size limit, are stored unchanged without thumbnails.
+///
+/// Quotas are checked against the uploaded size beforehand, while usage is recorded from the
+/// re-encoded size. Re-encoding may grow an image slightly, which can overshoot a quota by at
+/// most `service_max_image_size` per image.
+pub async fn save_upload(
+    database: &Database,
+    resource_id: ResourceId,
+    meta: ResourceMeta,
+    user_id: String,
+    written: Written,
+) -> Result<ResourceDescriptor, Error> {
+    let avatar = resource::is_user_avatar(&resource_id, None);
+    let max_size = config::get().service_max_image_size;
+
+    if avatar && written.size > max_size {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Avatar exceeds the maximum size of {max_size} bytes"),
+        ));
+    }
+
+    let processed =
+        if avatar || (written.content_type.starts_with("image/") && written.size <= max_size) {
+            let data = read_written(&resource_id, &meta, &user_id, &written).await?;
+
+            let result = tokio::task::spawn_blocking(move || process(data, avatar))
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed joining image processing: {e}");
+                    Error::new(ErrorCode::Internal, "Failed to process image")
+                })?;
+
+            match result {
+                Ok(processed) => Some(processed),
+                Err(err) if avatar => return Err(err),
+                Err(err) => {
+                    tracing::debug!("Storing image without processing: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+    let Some(processed) = processed else {
+        let desc = resource::save(
+            database,
+            ResourceDescriptor::written(resource_id, meta, user_id, written)?,
+        )
+        .await?;
+
+        // A replaced image may have left thumbnails behind
+        delete_thumbnails(database, &desc.resource_id).await?;
+
+        return Ok(desc);
+    };
+
+    // The original blob is never referenced and swept later
+    let written = write_bytes(database, processed.data).await?;
+
+    let desc = resource::save(
+        database,
+        ResourceDescriptor::written(resource_id, meta, user_id, written)?,
+    )
+    .await?;
+
+    for (size, data) in processed.thumbnails {
+        let written = write_bytes(database, data).await?;
+
+        resource::save(
+            database,
+            ResourceDescriptor::written(
+                build_thumbnail_id(&desc.resource_id, size),
+                ResourceMeta {
+                    size: 0,
+                    timestamp: desc.meta.timestamp.clone(),
+                    metadata: Default::default(),
+                },
+                desc.user_id.clone(),
+                written,
+            )?,
+        )
+        .await?;
+    }
+
+    Ok(desc)
+}
+
+/// Removes the thumbnails derived from a resource.
+pub async fn delete_thumbnails(database: &Database, resource_id: &ResourceId) -> Result<(), Error> {
+    for size in THUMBNAIL_SIZES {
+        let thumbnail = build_thumbnail_id(resource_id, size);
+
+        if resource::exists(database, &thumbnail).await? {
+            resource::remove(database, &thumbnail).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the ID of the thumbnail of a resource with the given edge length.
+///
+/// The key is a derived one, see [`resource::is_derived`], so clients cannot write or delete it.
+pub fn build_thumbnail_id(resource_id: &ResourceId, size: u32) -> ResourceId {
+    ResourceId {
+        namespace: resource_id.namespace.clone(),
+        key: format!(".{}.thumb-{size}.png", resource_id.key),
+    }
+}
+
+async fn read_written(
+    resource_id: &ResourceId,
+    meta: &ResourceMeta,
+    user_id: &str,
+    written: &Written,
+) -> Result<Vec<u8>, Error> {
+    let desc = ResourceDescriptor::written(
+        resource_id.clone(),
+        meta.clone(),
+        user_id.to_string(),
+        written.clone(),
+    )?;
+
+    let mut stream = resource::read(&desc, 0..written.size).await?;
+    let mut data = Vec::with_capacity(written.size as usize);
+
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+
+    Ok(data)
+}
+
+async fn write_bytes(database: &Database, data: Vec<u8>) -> Result<Written, Error> {
+    let size = data.len() as u64;
+
+    resource::write(database, Some(size), VecStream::once(Ok(data))).await
+}
+
+/// Decodes, checks and re-encodes an image and renders its thumbnails.
+///
+/// Blocks for the whole decoding and encoding, so it must run on the blocking pool.
+fn process(data: Vec<u8>, avatar: bool) -> Result<Processed, Error> {
+    let config = config::get();
+
+    // Only the first frame would be decoded, which drops the animation
+    if is_animated_webp(&data) {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Animated images are not supported",
+        ));
+    }
+
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|_| Error::new(ErrorCode::InvalidFormat, "Failed to read image"))?;
+
+    let format = reader
+        .format()
+        .filter(|format| IMAGE_FORMATS.contains(format))
+        .ok_or(Error::new(
+            ErrorCode::InvalidFormat,
+            "Image must be a PNG, JPEG or WebP file",
+        ))?;
+
+    if avatar && format != ImageFormat::Png {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Avatar must be a PNG file",
+        ));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.service_max_image_dimension);
+    limits.max_image_height = Some(config.service_max_image_dimension);
+    reader.limits(limits);
+
+    let decode_error = |err: ImageError| {
+        Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Failed to decode image: {err}"),
+        )
+    };
+
+    let mut decoder = reader.into_decoder().map_err(decode_error)?;
+    let orientation = decoder.orientation().map_err(decode_error)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(decode_error)?;
+
+    // The EXIF orientation is dropped with all other metadata, so it is applied to the pixels
+    image.apply_orientation(orientation);
+
+    let max_dimension = config.service_max_avatar_dimension;
+
+    if avatar && (image.width() > max_dimension || image.height() > max_dimension) {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Avatar must be at most {max_dimension}x{max_dimension} pixels"),
+        ));
+    }
+
+    // Encoding the decoded pixels drops EXIF and all other metadata
+    let data = encode(&image, format)?;
+
+    let thumbnails = THUMBNAIL_SIZES
+        .into_iter()
+        .map(|size| {
+            Ok((
+                size,
+                encode(&image.thumbnail(size, size), ImageFormat::Png)?,
+            ))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(Processed { data, thumbnails })
+}
+
+/// Checks the animation flag of the extended header of a WebP file.
+fn is_animated_webp(data: &[u8]) -> bool {
+    data.len() > 20
+        && &data[0..4] == b"RIFF"
+        && &data[8..12] == b"WEBP"
+        && &data[12..16] == b"VP8X"
+        && data[20] & 0x02 != 0
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, Error> {
+    let mut data = Cursor::new(Vec::new());
+
+    let result = match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode_image(&image.to_rgb8())
+        }
+        _ => image.write_to(&mut data, format),
+    };
+
+    result.map_err(|e| {
+        tracing::error!("Failed encoding image: {e}");
+        Error::new(ErrorCode::Internal, "Failed to process image")
+    })?;
+
+    Ok(data.into_inner())
+}
+
+struct Processed {
+    data: Vec<u8>,
+    /// Edge length and PNG data of every thumbnail.
+    thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        encode(&DynamicImage::new_rgb8(width, height), ImageFormat::Png).unwrap()
+    }
+
+    fn webp_header(flags: u8) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0".to_vec();
+        data.extend_from_slice(&[flags, 0, 0, 0, 0, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn animated_webp_detected_from_header() {
+        assert!(is_animated_webp(&webp_header(0x02)));
+        assert!(is_animated_webp(&webp_header(0x12)));
+        assert!(!is_animated_webp(&webp_header(0x10)));
+        assert!(!is_animated_webp(&webp_header(0x02)[..20]));
+        assert!(!is_animated_webp(&png(1, 1)));
+    }
+
+    #[test]
+    fn process_renders_thumbnails() {
+        config::init_test();
+
+        let processed = process(png(300, 150), false).unwrap();
+
+        let sizes: Vec<u32> = processed.thumbnails.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, THUMBNAIL_SIZES);
+
+        for (size, data) in processed.thumbnails {
+            let thumbnail = image::load_from_memory(&data).unwrap();
+            assert_eq!((thumbnail.width(), thumbnail.height()), (size, size / 2));
+        }
+
+        let image = image::load_from_memory(&processed.data).unwrap();
+        assert_eq!((image.width(), image.height()), (300, 150));
+    }
+
+    #[test]
+    fn process_keeps_jpeg_format() {
+        config::init_test();
+
+        let jpeg = encode(&DynamicImage::new_rgb8(16, 16), ImageFormat::Jpeg).unwrap();
+        let processed = process(jpeg, false).unwrap();
+
+        assert_eq!(
+            image::guess_format(&processed.data).unwrap(),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn process_rejects_invalid_avatars() {
+        config::init_test();
+
+        let max = config::get().service_max_avatar_dimension;
+        let jpeg = encode(&DynamicImage::new_rgb8(16, 16), ImageFormat::Jpeg).unwrap();
+
+        assert!(process(png(max, max), true).is_ok());
+
+        for data in [png(max + 1, 1), jpeg] {
+            assert_eq!(
+                process(data, true).err().unwrap().code(),
+                ErrorCode::InvalidFormat
+            );
+        }
+    }
+
+    #[test]
+    fn process_rejects_unsupported_data() {
+        config::init_test();
+
+        for data in [b"not an image".to_vec(), webp_header(0x02)] {
+            assert_eq!(
+                process(data, false).err().unwrap().code(),
+                ErrorCode::InvalidFormat
+            );
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::utils;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Content, Timestamp};
+use surrealdb::types::SurrealValue;
+
+pub const ID_LENGTH: usize = 12;
+
+pub const REPORT_OPEN: &str = "open";
+pub const REPORT_RESOLVED: &str = "resolved";
+pub const REPORT_DISMISSED: &str = "dismissed";
+
+pub const SANCTION_MUTE: &str = "mute";
+pub const SANCTION_TIMEOUT: &str = "timeout";
+pub const SANCTION_BAN: &str = "ban";
+
+pub fn build_id() -> String {
+    nanoid::nanoid!(ID_LENGTH)
+}
+
+pub async fn create_report(database: &Database, report: Report) -> Result<Report, Error> {
+    let report: Option<Report> = database
+        .create(("report", report.report_id.as_str()))
+        .content(report)
+        .await?;
+
+    report.ok_or(Error::new(ErrorCode::Internal, "Failed to create report"))
+}
+
+pub async fn get_report(database: &Database, report_id: &str) -> Result<Option<Report>, Error> {
+    let report: Option<Report> = database.select(("report", report_id)).await?;
+
+    Ok(report)
+}
+
+pub async fn get_reports(
+    database: &Database,
+    status: &str,
+    limit: u32,
+) -> Result<Vec<Report>, Error> {
+    let reports: Vec<Report> = database
+        .query(
+            r#"
+SELECT *
+FROM report
+WHERE status = $status
+ORDER BY created_at.millis ASC
+LIMIT $limit;
+"#,
+        )
+        .bind(("status", status.to_string()))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(reports)
+}
+
+pub async fn update_report(database: &Database, report: Report) -> Result<Report, Error> {
+    let report: Option<Report> = database
+        .update(("report", report.report_id.as_str()))
+        .content(report)
+        .await?;
+
+    report.ok_or(Error::new(ErrorCode::NotFound, "Report not found"))
+}
+
+pub async fn create_sanction(database: &Database, sanction: Sanction) -> Result<Sanction, Error> {
+    let sanction: Option<Sanction> = database
+        .create(("sanction", sanction.sanction_id.as_str()))
+        .content(sanction)
+        .await?;
+
+    sanction.ok_or(Error::new(ErrorCode::Internal, "Failed to create sanction"))
+}
+
+pub async fn get_sanction(
+    database: &Database,
+    sanction_id: &str,
+) -> Result<Option<Sanction>, Error> {
+    let sanction: Option<Sanction> = database.select(("sanction", sanction_id)).await?;
+
+    Ok(sanction)
+}
+
+pub async fn revoke_sanction(database: &Database, sanction_id: &str) -> Result<(), Error> {
+    let mut sanction = get_sanction(database, sanction_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Sanction not found"))?;
+
+    sanction.revoked = true;
+
+    let _: Option<Sanction> = database
+        .update(("sanction", sanction_id))
+        .content(sanction)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_user_sanctions(
+    database: &Database,
+    user_id: &str,
+) -> Result<Vec<Sanction>, Error> {
+    let sanctions: Vec<Sanction> = database
+        .query(
+            r#"
+SELECT *
+FROM sanction
+WHERE user_id = $user
+ORDER BY created_at.millis DESC;
+"#,
+        )
+        .bind(("user", user_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(sanctions)
+}
+
+/// Returns the active sanction of the given kinds, a `channel_id` of `None` means server-wide.
+async fn get_active_sanction(
+    database: &Database,
+    user_id: &str,
+    channel_id: Option<&str>,
+    kinds: &[&str],
+) -> Result<Option<Sanction>, Error> {
+    let sanction: Option<Sanction> = database
+        .query(
+            r#"
+SELECT *
+FROM sanction
+WHERE user_id = $user
+  AND channel_id = $channel
+  AND kind IN $kinds
+  AND revoked = false
+  AND (expires_at.millis = 0 OR expires_at.millis > $now)
+ORDER BY expires_at.millis DESC
+LIMIT 1;
+"#,
+        )
+        .bind(("user", user_id.to_string()))
+        .bind(("channel", channel_id.unwrap_or_default().to_string()))
+        .bind((
+            "kinds",
+            kinds
+                .iter()
+                .map(|kind| kind.to_string())
+                .collect::<Vec<_>>(),
+        ))
+        .bind(("now", utils::get_timestamp().millis))
+        .await?
+        .take(0)?;
+
+    Ok(sanction)
+}
+
+/// Fails if the user is banned from the server.
+pub async fn check_banned(database: &Database, user_id: &str) -> Result<(), Error> {
+    if let Some(ban) = get_active_sanction(database, user_id, None, &[SANCTION_BAN]).await? {
+        return Err(Error::new(
+            ErrorCode::Unauthorized,
+            format!("User is banned: {}", ban.describe()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails if the user is muted or timed out in the channel.
+pub async fn check_can_post(
+    database: &Database,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<(), Error> {
+    if let Some(sanction) = get_active_sanction(
+        database,
+        user_id,
+        Some(channel_id),
+        &[SANCTION_MUTE, SANCTION_TIMEOUT],
+    )
+    .await?
+    {
+        return Err(Error::new(
+            ErrorCode::Unauthorized,
+            format!("User is muted in this channel: {}", sanction.describe()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// A user report of a message, waiting for review by a supervisor.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Report {
+    pub report_id: String,
+    pub message_id: String,
+    pub channel_id: String,
+    /// The user who reported the message.
+    pub reporter_id: String,
+    /// The author of the reported message.
+    pub user_id: String,
+    /// Copy of the message content at the time of the report.
+    pub content: Content,
+    pub reason: String,
+    pub status: String,
+    /// The supervisor who reviewed the report.
+    pub reviewer_id: String,
+    pub resolution: String,
+    pub created_at: Timestamp,
+}
+
+/// A mute, timeout or ban of a user.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Sanction {
+    pub sanction_id: String,
+    pub kind: String,
+    pub user_id: String,
+    /// Channel the sanction applies to, empty for server-wide bans.
+    pub channel_id: String,
+    pub reason: String,
+    /// The supervisor who issued the sanction.
+    pub issuer_id: String,
+    pub created_at: Timestamp,
+    /// Time at which the sanction ends, `0` if it never does.
+    pub expires_at: Timestamp,
+    pub revoked: bool,
+}
+
+impl Sanction {
+    fn describe(&self) -> String {
+        if self.expires_at.millis == 0 {
+            format!("{} (permanent)", self.reason)
+        } else {
+            format!("{} (until {} ms)", self.reason, self.expires_at.millis)
+        }
+    }
+}
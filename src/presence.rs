@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Maximum number of presence events buffered before slow subscribers start lagging.
+pub const PRESENCE_BUFFER_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    Online,
+    Idle,
+    DoNotDisturb,
+    Offline,
+}
+
+#[derive(Clone, Debug)]
+pub enum PresenceEvent {
+    Status { user_id: String, status: Status },
+    Typing { user_id: String, channel_id: String },
+}
+
+/// In-memory user presence.
+///
+/// Presence is never persisted and entries expire if no heartbeat arrives in time.
+#[derive(Clone, Debug)]
+pub struct Presence {
+    users: Arc<RwLock<HashMap<String, Entry>>>,
+    events: broadcast::Sender<PresenceEvent>,
+    timeout: Duration,
+    idle: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    /// Status requested by the client.
+    requested: Status,
+    /// Status last announced to subscribers.
+    status: Status,
+    connections: usize,
+    last_seen: Instant,
+    last_active: Instant,
+}
+
+impl Entry {
+    fn effective_status(&self, now: Instant, timeout: Duration, idle: Duration) -> Status {
+        if self.connections == 0 || now.duration_since(self.last_seen) > timeout {
+            Status::Offline
+        } else if self.requested == Status::Online && now.duration_since(self.last_active) > idle {
+            Status::Idle
+        } else {
+            self.requested
+        }
+    }
+}
+
+impl Presence {
+    pub fn new(timeout: Duration, idle: Duration) -> Self {
+        Self {
+            users: Arc::new(RwLock::new(HashMap::new())),
+            events: broadcast::channel(PRESENCE_BUFFER_SIZE).0,
+            timeout,
+            idle,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn status(&self, user_id: &str) -> Status {
+        let users = self.users.read().expect("Presence poisoned");
+
+        users
+            .get(user_id)
+            .map(|entry| entry.effective_status(Instant::now(), self.timeout, self.idle))
+            .unwrap_or(Status::Offline)
+    }
+
+    pub fn connect(&self, user_id: &str) {
+        let now = Instant::now();
+
+        self.update(user_id, |entry| {
+            entry.connections += 1;
+            entry.last_seen = now;
+            entry.last_active = now;
+        });
+    }
+
+    pub fn disconnect(&self, user_id: &str) {
+        self.update(user_id, |entry| {
+            entry.connections = entry.connections.saturating_sub(1);
+        });
+    }
+
+    /// Records a heartbeat, `active` tells if the user interacted with the client since the last one.
+    pub fn heartbeat(&self, user_id: &str, requested: Status, active: bool) {
+        let now = Instant::now();
+
+        self.update(user_id, |entry| {
+            entry.requested = requested;
+            entry.last_seen = now;
+
+            if active {
+                entry.last_active = now;
+            }
+        });
+    }
+
+    pub fn typing(&self, user_id: &str, channel_id: &str) {
+        let now = Instant::now();
+
+        self.update(user_id, |entry| {
+            entry.last_seen = now;
+            entry.last_active = now;
+        });
+
+        let _ = self.events.send(PresenceEvent::Typing {
+            user_id: user_id.to_string(),
+            channel_id: channel_id.to_string(),
+        });
+    }
+
+    /// Announces status changes caused by timeouts and forgets offline users without open
+    /// connections.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        let mut users = self.users.write().expect("Presence poisoned");
+
+        for (user_id, entry) in users.iter_mut() {
+            let status = entry.effective_status(now, self.timeout, self.idle);
+
+            if status != entry.status {
+                entry.status = status;
+                self.announce(user_id, status);
+            }
+        }
+
+        // Connected users keep their entry, so the connection count survives a timeout
+        users.retain(|_, entry| entry.status != Status::Offline || entry.connections > 0);
+    }
+
+    fn update(&self, user_id: &str, f: impl FnOnce(&mut Entry)) {
+        let now = Instant::now();
+        let mut users = self.users.write().expect("Presence poisoned");
+
+        let entry = users.entry(user_id.to_string()).or_insert_with(|| Entry {
+            requested: Status::Online,
+            status: Status::Offline,
+            connections: 0,
+            last_seen: now,
+            last_active: now,
+        });
+
+        f(entry);
+
+        let status = entry.effective_status(now, self.timeout, self.idle);
+
+        if status != entry.status {
+            entry.status = status;
+            self.announce(user_id, status);
+        }
+    }
+
+    fn announce(&self, user_id: &str, status: Status) {
+        // Sending only fails if nobody is subscribed
+        let _ = self.events.send(PresenceEvent::Status {
+            user_id: user_id.to_string(),
+            status,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+    const IDLE: Duration = Duration::from_millis(50);
+
+    fn entry(requested: Status, connections: usize, now: Instant) -> Entry {
+        Entry {
+            requested,
+            status: Status::Offline,
+            connections,
+            last_seen: now,
+            last_active: now,
+        }
+    }
+
+    fn next_status(events: &mut broadcast::Receiver<PresenceEvent>) -> Option<Status> {
+        match events.try_recv().ok()? {
+            PresenceEvent::Status { status, .. } => Some(status),
+            PresenceEvent::Typing { .. } => None,
+        }
+    }
+
+    #[test]
+    fn effective_status_follows_requested() {
+        let now = Instant::now();
+
+        for status in [Status::Online, Status::DoNotDisturb] {
+            assert_eq!(
+                entry(status, 1, now).effective_status(now, TIMEOUT, IDLE),
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn effective_status_is_offline_without_connections_or_heartbeats() {
+        let now = Instant::now();
+
+        assert_eq!(
+            entry(Status::Online, 0, now).effective_status(now, TIMEOUT, IDLE),
+            Status::Offline
+        );
+        assert_eq!(
+            entry(Status::Online, 1, now).effective_status(now + TIMEOUT * 2, TIMEOUT, IDLE),
+            Status::Offline
+        );
+    }
+
+    #[test]
+    fn effective_status_turns_idle_when_inactive() {
+        let now = Instant::now();
+        let mut online = entry(Status::Online, 1, now);
+        online.last_seen = now + IDLE * 2;
+
+        assert_eq!(
+            online.effective_status(now + IDLE * 2, TIMEOUT, IDLE),
+            Status::Idle
+        );
+
+        // Do not disturb is kept even if the user is inactive
+        let mut dnd = online.clone();
+        dnd.requested = Status::DoNotDisturb;
+
+        assert_eq!(
+            dnd.effective_status(now + IDLE * 2, TIMEOUT, IDLE),
+            Status::DoNotDisturb
+        );
+    }
+
+    #[test]
+    fn connect_and_disconnect_announce_status() {
+        let presence = Presence::new(TIMEOUT, IDLE);
+        let mut events = presence.subscribe();
+
+        presence.connect("user");
+        assert_eq!(presence.status("user"), Status::Online);
+        assert_eq!(next_status(&mut events), Some(Status::Online));
+
+        // A second connection changes nothing
+        presence.connect("user");
+        presence.disconnect("user");
+        assert_eq!(presence.status("user"), Status::Online);
+        assert_eq!(next_status(&mut events), None);
+
+        presence.disconnect("user");
+        assert_eq!(presence.status("user"), Status::Offline);
+        assert_eq!(next_status(&mut events), Some(Status::Offline));
+    }
+
+    #[test]
+    fn sweep_announces_idle_users() {
+        let presence = Presence::new(TIMEOUT, IDLE);
+        presence.connect("user");
+        let mut events = presence.subscribe();
+
+        thread::sleep(IDLE * 2);
+        presence.heartbeat("user", Status::Online, false);
+        assert_eq!(next_status(&mut events), Some(Status::Idle));
+
+        presence.heartbeat("user", Status::Online, true);
+        assert_eq!(next_status(&mut events), Some(Status::Online));
+
+        thread::sleep(IDLE * 2);
+        presence.sweep();
+        assert_eq!(next_status(&mut events), Some(Status::Idle));
+    }
+
+    #[test]
+    fn sweep_announces_timeouts_and_forgets_disconnected_users() {
+        let presence = Presence::new(IDLE, IDLE * 4);
+        presence.connect("connected");
+        presence.connect("disconnected");
+        presence.disconnect("disconnected");
+        let mut events = presence.subscribe();
+
+        thread::sleep(IDLE * 2);
+        presence.sweep();
+
+        assert_eq!(presence.status("connected"), Status::Offline);
+        assert_eq!(next_status(&mut events), Some(Status::Offline));
+        assert_eq!(next_status(&mut events), None);
+
+        // Only the user with an open connection is kept
+        let users = presence.users.read().unwrap();
+        assert!(users.contains_key("connected"));
+        assert!(!users.contains_key("disconnected"));
+    }
+}
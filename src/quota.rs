@@ -0,0 +1,226 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::resource::ResourceDescriptor;
+use crate::{chat, config, resource};
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::user::v1::UserRole;
+use elysium_rust::{ResourceId, User};
+use surrealdb::types::SurrealValue;
+
+/// Returns the storage quota of a user in bytes, `0` if unlimited.
+pub async fn get_user_limit(database: &Database, user: &User) -> Result<u64, Error> {
+    if let Some(quota) = get_quota(database, &user_key(&user.user_id)).await? {
+        return Ok(quota.limit);
+    }
+
+    let config = config::get();
+
+    Ok(if user.role >= UserRole::Admin as i32 {
+        config.service_admin_quota
+    } else if user.role >= UserRole::Supervisor as i32 {
+        config.service_supervisor_quota
+    } else {
+        config.service_user_quota
+    })
+}
+
+/// Returns the storage quota of a channel in bytes, `0` if unlimited.
+pub async fn get_channel_limit(database: &Database, channel_id: &str) -> Result<u64, Error> {
+    Ok(get_quota(database, &channel_key(channel_id))
+        .await?
+        .map(|quota| quota.limit)
+        .unwrap_or(config::get().service_channel_quota))
+}
+
+/// Overrides the storage quota of a user, or resets it to the role default if `limit` is `None`.
+pub async fn set_user_limit(
+    database: &Database,
+    user_id: &str,
+    limit: Option<u64>,
+) -> Result<(), Error> {
+    set_quota(database, user_key(user_id), limit).await
+}
+
+/// Overrides the storage quota of a channel, or resets it to the default if `limit` is `None`.
+pub async fn set_channel_limit(
+    database: &Database,
+    channel_id: &str,
+    limit: Option<u64>,
+) -> Result<(), Error> {
+    set_quota(database, channel_key(channel_id), limit).await
+}
+
+/// Returns the number of bytes stored by a user.
+pub async fn get_user_usage(database: &Database, user_id: &str) -> Result<u64, Error> {
+    get_usage(database, &user_key(user_id)).await
+}
+
+/// Returns the number of bytes stored in a channel namespace.
+pub async fn get_channel_usage(database: &Database, channel_id: &str) -> Result<u64, Error> {
+    get_usage(database, &channel_key(channel_id)).await
+}
+
+/// Checks that storing `size` bytes as `resource_id` keeps the user and channel within their
+/// quotas, counting the resource it would replace as freed.
+pub async fn check(
+    database: &Database,
+    user: &User,
+    resource_id: &ResourceId,
+    size: u64,
+) -> Result<(), Error> {
+    let replaced = resource::get(database, resource_id).await?;
+
+    // Replacing a resource keeps its original uploader, who is charged for the new size
+    let user_id = replaced
+        .as_ref()
+        .map(|desc| desc.user_id.as_str())
+        .unwrap_or(&user.user_id);
+    let freed = replaced
+        .as_ref()
+        .map(|desc| desc.meta.size.max(0) as u64)
+        .unwrap_or(0);
+
+    let limit = if user_id == user.user_id {
+        get_user_limit(database, user).await?
+    } else {
+        match crate::user::get(database, user_id).await? {
+            Some(owner) => get_user_limit(database, &owner).await?,
+            None => 0,
+        }
+    };
+
+    if exceeds(get_user_usage(database, user_id).await?, freed, size, limit) {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Resource exceeds the user storage quota of {limit} bytes"),
+        ));
+    }
+
+    if chat::channel_exists(database, &resource_id.namespace).await? {
+        let limit = get_channel_limit(database, &resource_id.namespace).await?;
+
+        if exceeds(
+            get_channel_usage(database, &resource_id.namespace).await?,
+            freed,
+            size,
+            limit,
+        ) {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                format!("Resource exceeds the channel storage quota of {limit} bytes"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the usage changes of adding a resource to its uploader and namespace, or of removing
+/// it if `sign` is negative. Derived resources are not counted.
+///
+/// The changes are applied by the transaction changing the resource, see [`resource::create`].
+pub fn changes(desc: &ResourceDescriptor, sign: i64) -> Vec<UsageChange> {
+    if resource::is_derived(&desc.resource_id) {
+        return Vec::new();
+    }
+
+    let delta = sign * desc.meta.size.max(0) as i64;
+
+    vec![
+        UsageChange {
+            key: user_key(&desc.user_id),
+            delta,
+        },
+        UsageChange {
+            key: channel_key(&desc.resource_id.namespace),
+            delta,
+        },
+    ]
+}
+
+fn exceeds(used: u64, freed: u64, size: u64, limit: u64) -> bool {
+    limit > 0 && used.saturating_sub(freed) + size > limit
+}
+
+async fn get_quota(database: &Database, key: &str) -> Result<Option<StorageQuota>, Error> {
+    let quota: Option<StorageQuota> = database.select(("storage_quota", key)).await?;
+
+    Ok(quota)
+}
+
+async fn set_quota(database: &Database, key: String, limit: Option<u64>) -> Result<(), Error> {
+    match limit {
+        Some(limit) => {
+            let _: Option<StorageQuota> = database
+                .upsert(("storage_quota", key.as_str()))
+                .content(StorageQuota { limit })
+                .await?;
+        }
+        None => {
+            let _: Option<StorageQuota> = database.delete(("storage_quota", key.as_str())).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn get_usage(database: &Database, key: &str) -> Result<u64, Error> {
+    let usage: Option<StorageUsage> = database.select(("storage_usage", key)).await?;
+
+    // Usage can briefly drop below zero while concurrent changes are recorded
+    Ok(usage.map(|usage| usage.used.max(0) as u64).unwrap_or(0))
+}
+
+fn user_key(user_id: &str) -> String {
+    format!("user:{user_id}")
+}
+
+/// Usage is tracked for every namespace, but only limited for channels.
+fn channel_key(namespace: &str) -> String {
+    format!("namespace:{namespace}")
+}
+
+/// An overridden storage quota of a user or channel.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct StorageQuota {
+    /// Quota in bytes, `0` for no limit.
+    pub limit: u64,
+}
+
+/// Change of the number of bytes stored by a user or in a namespace.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct UsageChange {
+    /// Key of the changed [`StorageUsage`] record.
+    pub key: String,
+    pub delta: i64,
+}
+
+/// Number of bytes stored by a user or in a namespace.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct StorageUsage {
+    pub used: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceeds_without_limit() {
+        assert!(!exceeds(u64::MAX / 2, 0, u64::MAX / 2, 0));
+    }
+
+    #[test]
+    fn exceeds_only_past_limit() {
+        assert!(!exceeds(60, 0, 40, 100));
+        assert!(exceeds(60, 0, 41, 100));
+    }
+
+    #[test]
+    fn exceeds_counts_freed_bytes() {
+        // Replacing a resource frees the bytes of the replaced one
+        assert!(!exceeds(90, 30, 40, 100));
+        assert!(exceeds(90, 29, 40, 100));
+        assert!(!exceeds(10, 30, 100, 100));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Fixed-window rate limiter keyed by arbitrary strings.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    windows: Arc<Mutex<HashMap<String, Window>>>,
+}
+
+#[derive(Clone, Debug)]
+struct Window {
+    started: Instant,
+    period: Duration,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            windows: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Counts a hit for `key`, allowing `limit` hits per `period`.
+    ///
+    /// Returns how long to wait if the limit is exceeded.
+    pub fn check(&self, key: &str, limit: u32, period: Duration) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("Rate limiter poisoned");
+
+        let window = windows.entry(key.to_string()).or_insert(Window {
+            started: now,
+            period,
+            count: 0,
+        });
+
+        if now.duration_since(window.started) >= period {
+            window.started = now;
+            window.count = 0;
+        }
+
+        window.period = period;
+
+        if window.count >= limit {
+            return Err(period - now.duration_since(window.started));
+        }
+
+        window.count += 1;
+
+        Ok(())
+    }
+
+    /// Forgets all windows that are over.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("Rate limiter poisoned");
+
+        windows.retain(|_, window| now.duration_since(window.started) < window.period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const PERIOD: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn check_allows_limit_per_window() {
+        let limiter = RateLimiter::new();
+
+        for _ in 0..3 {
+            assert!(limiter.check("key", 3, PERIOD).is_ok());
+        }
+
+        let wait = limiter.check("key", 3, PERIOD).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= PERIOD);
+    }
+
+    #[test]
+    fn check_counts_keys_separately() {
+        let limiter = RateLimiter::new();
+
+        assert!(limiter.check("a", 1, PERIOD).is_ok());
+        assert!(limiter.check("a", 1, PERIOD).is_err());
+        assert!(limiter.check("b", 1, PERIOD).is_ok());
+    }
+
+    #[test]
+    fn check_resets_expired_windows() {
+        let limiter = RateLimiter::new();
+
+        assert!(limiter.check("key", 1, PERIOD).is_ok());
+        assert!(limiter.check("key", 1, PERIOD).is_err());
+
+        thread::sleep(PERIOD * 2);
+
+        assert!(limiter.check("key", 1, PERIOD).is_ok());
+    }
+
+    #[test]
+    fn sweep_forgets_expired_windows() {
+        let limiter = RateLimiter::new();
+
+        limiter.check("short", 1, PERIOD).unwrap();
+        limiter.check("long", 1, PERIOD * 100).unwrap();
+
+        thread::sleep(PERIOD * 2);
+        limiter.sweep();
+
+        let windows = limiter.windows.lock().unwrap();
+        assert!(!windows.contains_key("short"));
+        assert!(windows.contains_key("long"));
+    }
+}
@@ -1,10 +1,14 @@
 use crate::database::Database;
 use crate::error::Error;
-use crate::utils::RESOURCE_CHUNK_SIZE;
-use crate::{chat, config, user, utils};
+use crate::store::{self, ByteStream};
+use crate::utils::{RESOURCE_CHUNK_SIZE, VecStream};
+use crate::{chat, config, media, quota, user, utils};
 use elysium_rust::chat::v1::ChannelPermission;
 use elysium_rust::common::v1::ErrorCode;
-use elysium_rust::{ResourceId, ResourceMeta};
+use elysium_rust::user::v1::UserRole;
+use elysium_rust::{ResourceId, ResourceMeta, Timestamp};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use surrealdb::types::SurrealValue;
 use tokio::fs;
@@ -18,6 +22,33 @@ pub const BUILTIN_NAMESPACE: &str = "elysium";
 /// Built-in user icon key.
 pub const DEFAULT_ICON_KEY: &str = "default_icon.png";
 
+/// Metadata key of the MIME type of a resource.
+pub const CONTENT_TYPE_METADATA_KEY: &str = "content_type";
+
+/// Content type of data which could not be identified.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Content type of data without magic bytes which is valid UTF-8.
+const TEXT_CONTENT_TYPE: &str = "text/plain";
+
+/// Number of leading bytes the content type is detected from.
+const SNIFF_LENGTH: usize = 8192;
+
+/// Metadata key under which the SHA-256 digest of a resource is returned to clients.
+pub const DIGEST_METADATA_KEY: &str = "sha256";
+
+/// Metadata key of the entity tag in download meta frames, see [`etag`].
+pub const ETAG_METADATA_KEY: &str = "etag";
+
+/// Metadata key of the first byte served by a download.
+pub const RANGE_OFFSET_METADATA_KEY: &str = "range_offset";
+
+/// Metadata key of the number of bytes served by a download.
+pub const RANGE_LENGTH_METADATA_KEY: &str = "range_length";
+
+/// Metadata key set to `true` if a download was skipped because the client's copy is current.
+pub const NOT_MODIFIED_METADATA_KEY: &str = "not_modified";
+
 pub async fn create(
     database: &Database,
     desc: ResourceDescriptor,
@@ -29,8 +60,170 @@ pub async fn create(
         ));
     }
 
-    if !utils::is_valid_file_name(&desc.resource_id.namespace)
-        || !utils::is_valid_file_name(&desc.resource_id.key)
+    if !is_derived(&desc.resource_id) {
+        validate_id(&desc.resource_id)?;
+    }
+
+    check_content_type(&desc)?;
+
+    // Resources written before blob storage have no blob
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+CREATE type::record('resource', $id) CONTENT $desc;
+IF $digest != '' {
+    UPDATE type::record('blob', $digest) SET refs += 1, touched_at = $now;
+};
+FOR $change IN $usage {
+    UPSERT type::record('storage_usage', $change.key) SET used = (used ?? 0) + $change.delta;
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", construct_id(&desc.resource_id)))
+        .bind(("digest", desc.digest.clone()))
+        .bind(("now", utils::get_timestamp()))
+        .bind(("usage", quota::changes(&desc, 1)))
+        .bind(("desc", desc.clone()))
+        .await?
+        .check()?;
+
+    Ok(desc)
+}
+
+/// Creates or replaces a resource, keeping the original uploader of replaced resources.
+pub async fn save(
+    database: &Database,
+    mut desc: ResourceDescriptor,
+) -> Result<ResourceDescriptor, Error> {
+    if let Some(existing) = get(database, &desc.resource_id).await? {
+        desc.user_id = existing.user_id;
+        update(database, desc).await
+    } else {
+        create(database, desc).await
+    }
+}
+
+pub async fn update(
+    database: &Database,
+    desc: ResourceDescriptor,
+) -> Result<ResourceDescriptor, Error> {
+    let previous = get(database, &desc.resource_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Resource not found"))?;
+
+    check_content_type(&desc)?;
+
+    // The blob released is the one actually replaced, even if the resource changed meanwhile.
+    // Acquiring first keeps a blob shared by both versions from dropping to no references.
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+LET $previous = (UPDATE ONLY type::record('resource', $id) CONTENT $desc RETURN BEFORE);
+IF $previous = NONE {
+    THROW 'Resource not found';
+};
+IF $digest != '' {
+    UPDATE type::record('blob', $digest) SET refs += 1, touched_at = $now;
+};
+IF $previous.digest != '' {
+    UPDATE type::record('blob', $previous.digest) SET refs -= 1, touched_at = $now;
+};
+FOR $change IN $usage {
+    UPSERT type::record('storage_usage', $change.key) SET used = (used ?? 0) + $change.delta;
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", construct_id(&desc.resource_id)))
+        .bind(("digest", desc.digest.clone()))
+        .bind(("now", utils::get_timestamp()))
+        .bind((
+            "usage",
+            [quota::changes(&previous, -1), quota::changes(&desc, 1)].concat(),
+        ))
+        .bind(("desc", desc.clone()))
+        .await?
+        .check()?;
+
+    Ok(desc)
+}
+
+/// Checks the detected content type of a resource against the allowed and denied types of its
+/// namespace kind.
+pub fn check_content_type(desc: &ResourceDescriptor) -> Result<(), Error> {
+    let config = config::get();
+
+    let (allowed, denied) = if desc.resource_id.namespace == BUILTIN_NAMESPACE {
+        (
+            &config.service_builtin_allowed_types,
+            &config.service_builtin_denied_types,
+        )
+    } else if desc.resource_id.namespace.starts_with("user.") {
+        (
+            &config.service_avatar_allowed_types,
+            &config.service_avatar_denied_types,
+        )
+    } else {
+        (
+            &config.service_channel_allowed_types,
+            &config.service_channel_denied_types,
+        )
+    };
+
+    let content_type = desc.content_type.as_str();
+
+    if matches_content_type(denied, content_type)
+        || (!allowed.trim().is_empty() && !matches_content_type(allowed, content_type))
+    {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Content type '{content_type}' is not allowed in this namespace"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks whether a content type is in a comma separated list, which may contain wildcards such
+/// as `image/*`.
+fn matches_content_type(list: &str, content_type: &str) -> bool {
+    list.split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(kind) => content_type
+                .split('/')
+                .next()
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(kind)),
+            None => pattern.eq_ignore_ascii_case(content_type),
+        })
+}
+
+/// Detects the content type of data from its leading bytes.
+pub fn sniff(prefix: &[u8]) -> String {
+    if let Some(kind) = infer::get(prefix) {
+        return kind.mime_type().to_string();
+    }
+
+    if prefix.is_empty() {
+        return DEFAULT_CONTENT_TYPE.to_string();
+    }
+
+    // Text has no magic bytes, a character may only be cut off at the end of the prefix
+    match std::str::from_utf8(prefix) {
+        Ok(_) => TEXT_CONTENT_TYPE,
+        Err(err) if err.error_len().is_none() => TEXT_CONTENT_TYPE,
+        Err(_) => DEFAULT_CONTENT_TYPE,
+    }
+    .to_string()
+}
+
+pub fn validate_id(resource_id: &ResourceId) -> Result<(), Error> {
+    if !utils::is_valid_file_name(&resource_id.namespace)
+        || !utils::is_valid_file_name(&resource_id.key)
     {
         return Err(Error::new(
             ErrorCode::InvalidFormat,
@@ -38,12 +231,22 @@ pub async fn create(
         ));
     }
 
-    let desc: Option<ResourceDescriptor> = database
-        .insert(("resource", construct_id(&desc.resource_id)))
-        .content(desc)
-        .await?;
+    if resource_id.namespace.starts_with('.') || resource_id.key.starts_with('.') {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Resource ID cannot start with '.'",
+        ));
+    }
+
+    Ok(())
+}
 
-    Ok(desc.ok_or(Error::new(ErrorCode::Internal, "Failed to create resource"))?)
+/// Checks whether a resource was derived by the server from another one, like a thumbnail.
+///
+/// Derived keys start with '.', which [`validate_id`] rejects, so clients cannot write or delete
+/// them. They are neither listed nor counted towards quotas.
+pub fn is_derived(resource_id: &ResourceId) -> bool {
+    resource_id.key.starts_with('.')
 }
 
 pub async fn get(
@@ -62,32 +265,241 @@ pub async fn exists(database: &Database, resource_id: &ResourceId) -> Result<boo
     Ok(resource.is_some())
 }
 
+/// Returns a page of the resources in a namespace ordered by key, starting after `cursor`.
+///
+/// The returned cursor continues the listing, it is `None` once the namespace is exhausted.
+pub async fn get_resources(
+    database: &Database,
+    namespace: &str,
+    limit: u32,
+    cursor: &str,
+) -> Result<(Vec<ResourceDescriptor>, Option<String>), Error> {
+    let resources: Vec<ResourceDescriptor> = database
+        .query(
+            r#"
+SELECT *
+FROM resource
+WHERE resource_id.namespace = $namespace
+  AND resource_id.key > $cursor
+  AND !string::starts_with(resource_id.key, '.')
+ORDER BY resource_id.key ASC
+LIMIT $limit;
+"#,
+        )
+        .bind(("namespace", namespace.to_string()))
+        .bind(("cursor", cursor.to_string()))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    // A short page means there is nothing left
+    let next = if resources.len() < limit as usize {
+        None
+    } else {
+        resources.last().map(|desc| desc.resource_id.key.clone())
+    };
+
+    Ok((resources, next))
+}
+
+/// Deletes a resource together with the resources derived from it.
+pub async fn delete(database: &Database, resource_id: &ResourceId) -> Result<(), Error> {
+    remove(database, resource_id).await?;
+    media::delete_thumbnails(database, resource_id).await
+}
+
+/// Deletes a single resource, leaving resources derived from it alone.
+pub async fn remove(database: &Database, resource_id: &ResourceId) -> Result<(), Error> {
+    let desc = get(database, resource_id)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "Resource not found"))?;
+
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+LET $removed = (DELETE ONLY type::record('resource', $id) RETURN BEFORE);
+IF $removed != NONE AND $removed.digest != '' {
+    UPDATE type::record('blob', $removed.digest) SET refs -= 1, touched_at = $now;
+};
+IF $removed != NONE {
+    FOR $change IN $usage {
+        UPSERT type::record('storage_usage', $change.key) SET used = (used ?? 0) + $change.delta;
+    };
+};
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", construct_id(resource_id)))
+        .bind(("now", utils::get_timestamp()))
+        .bind(("usage", quota::changes(&desc, -1)))
+        .await?
+        .check()?;
+
+    // Resources written before blob storage have their own file
+    if let Err(e) = fs::remove_file(build_path(resource_id)).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::error!("Failed removing resource file: {e}");
+        return Err(Error::new(ErrorCode::Internal, "Failed to delete resource"));
+    }
+
+    Ok(())
+}
+
+/// Removes blobs which have not been referenced since `before`.
+///
+/// The grace period keeps blobs which were just written but not yet registered.
+pub async fn sweep_blobs(
+    database: &Database,
+    before: Timestamp,
+    limit: usize,
+) -> Result<usize, Error> {
+    let blobs: Vec<Blob> = database
+        .query(
+            r#"
+SELECT *
+FROM blob
+WHERE refs <= 0 AND touched_at.millis < $before
+LIMIT $limit;
+"#,
+        )
+        .bind(("before", before.millis))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    let mut removed = 0;
+
+    for blob in blobs {
+        // Only remove the blob if it was not referenced again in the meantime
+        let deleted: Option<Blob> = database
+            .query(
+                r#"
+DELETE type::record('blob', $digest)
+WHERE refs <= 0 AND touched_at.millis < $before
+RETURN BEFORE;
+"#,
+            )
+            .bind(("digest", blob.digest.clone()))
+            .bind(("before", before.millis))
+            .await?
+            .take(0)?;
+
+        if deleted.is_none() {
+            continue;
+        }
+
+        if let Err(err) = store::get().delete(&build_blob_key(&blob.digest)).await {
+            tracing::error!("Failed removing blob '{}': {err}", blob.digest);
+        }
+
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Checks that every attachment was uploaded by the given user into the channel's namespace.
+pub async fn validate_attachments(
+    database: &Database,
+    channel_id: &str,
+    user_id: &str,
+    attachments: &[ResourceId],
+) -> Result<(), Error> {
+    for attachment in attachments {
+        let desc = get(database, attachment)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Attachment not found"))?;
+
+        if desc.resource_id.namespace != channel_id {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                "Attachment does not belong to this channel",
+            ));
+        }
+
+        if desc.user_id != user_id {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "Attachment was uploaded by another user",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether a user may download a resource.
+///
+/// Everything in a namespace the user may list can be downloaded, as well as every user's avatar.
 pub async fn is_download_authorized(
     database: &Database,
     desc: &ResourceDescriptor,
     user: &str,
 ) -> Result<bool, Error> {
-    let mut authorized = false;
+    let user = user::get(database, user)
+        .await?
+        .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?;
+
+    Ok(is_user_avatar(&desc.resource_id, None)
+        || is_list_authorized(database, &desc.resource_id.namespace, &user.user_id).await?)
+}
+
+/// Checks whether a user may list the resources of a namespace.
+///
+/// Builtin resources are listed to everyone, channel resources to channel members and the
+/// resources of a user only to that user.
+pub async fn is_list_authorized(
+    database: &Database,
+    namespace: &str,
+    user: &str,
+) -> Result<bool, Error> {
+    if namespace == BUILTIN_NAMESPACE {
+        return Ok(true);
+    }
+
+    if let Some(channel) = chat::get_channel(database, namespace).await? {
+        return Ok(channel.members.contains_key(user));
+    }
+
+    Ok(namespace == format!("user.{user}"))
+}
+
+/// Checks whether a user may delete a resource.
+///
+/// Admins may delete everything, channel managers everything in their channel and uploaders
+/// what they uploaded, as long as they may still upload there.
+pub async fn is_delete_authorized(
+    database: &Database,
+    desc: &ResourceDescriptor,
+    user: &str,
+) -> Result<bool, Error> {
     let user = user::get(database, user)
         .await?
         .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?;
 
     if from_builtin(&desc.resource_id).is_some() {
-        authorized = true;
-    } else if let Some(channel) = chat::get_channel(database, &desc.resource_id.namespace).await?
-        && channel.members.contains_key(&user.user_id)
+        return Ok(false);
+    }
+
+    if user.role >= UserRole::Admin as i32 {
+        return Ok(true);
+    }
+
+    if let Some(channel) = chat::get_channel(database, &desc.resource_id.namespace).await?
+        && channel.members.get(&user.user_id).copied() == Some(ChannelPermission::Manager as i32)
     {
-        authorized = true;
-    } else if is_user_avatar(&desc.resource_id, None) {
-        authorized = true;
+        return Ok(true);
     }
 
-    Ok(authorized)
+    Ok(desc.user_id == user.user_id
+        && is_upload_authorized(database, &desc.resource_id, &user.user_id).await?)
 }
 
 pub async fn is_upload_authorized(
     database: &Database,
-    desc: &ResourceDescriptor,
+    resource_id: &ResourceId,
     user: &str,
 ) -> Result<bool, Error> {
     let mut authorized = false;
@@ -95,13 +507,12 @@ pub async fn is_upload_authorized(
         .await?
         .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?;
 
-    if let Some(channel) = chat::get_channel(database, &desc.resource_id.namespace).await? {
+    if let Some(channel) = chat::get_channel(database, &resource_id.namespace).await? {
         let perm =
             chat::get_channel_member_perm(database, &channel.channel_id, &user.user_id).await?;
 
         authorized = perm == ChannelPermission::Manager || perm == ChannelPermission::ReadWrite;
-    } else if is_user_avatar(&desc.resource_id, Some(&user.user_id))
-        && desc.resource_id.key.ends_with(".png")
+    } else if is_user_avatar(resource_id, Some(&user.user_id)) && resource_id.key.ends_with(".png")
     {
         authorized = true;
     }
@@ -109,41 +520,181 @@ pub async fn is_upload_authorized(
     Ok(authorized)
 }
 
-pub async fn read(id: ResourceId) -> Result<impl Stream<Item = Result<Vec<u8>, Error>>, Error> {
-    let path = build_path(&id);
+/// Streams the bytes of a resource within `range`, see [`resolve_range`].
+pub async fn read(desc: &ResourceDescriptor, range: Range<u64>) -> Result<ByteStream, Error> {
+    // Neither S3 nor seeking past the end of a file can serve an empty range
+    if range.is_empty() {
+        return Ok(Box::pin(VecStream::new(Vec::new())));
+    }
 
-    let file = fs::OpenOptions::new()
-        .read(true)
-        .write(false)
-        .create(false)
-        .append(false)
-        .open(path)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed opening read file: {e}");
-            Error::new(ErrorCode::Internal, "Failed to read resource")
-        })?;
+    if !desc.digest.is_empty() {
+        match store::get()
+            .read(&build_blob_key(&desc.digest), range.clone())
+            .await
+        {
+            Err(err) if err.code() == ErrorCode::NotFound => {}
+            result => return result,
+        }
+    }
 
-    let stream = ReaderStream::with_capacity(file, RESOURCE_CHUNK_SIZE).map(|res| {
-        res.map_err(|err| {
-            tracing::error!("Failed reading file: {err}");
-            Error::new(ErrorCode::Internal, "Failed to read resource")
-        })
-        .map(|by| by.to_vec())
-    });
+    // Resources written before blob storage have their own file
+    store::read_file(&build_path(&desc.resource_id), range).await
+}
+
+/// Resolves a requested byte range of a resource, reading until the end if `length` is `0`.
+///
+/// The range is clamped to the end of the resource, but must not start past it.
+pub fn resolve_range(
+    desc: &ResourceDescriptor,
+    offset: u64,
+    length: u64,
+) -> Result<Range<u64>, Error> {
+    let size = desc.meta.size.max(0) as u64;
 
-    Ok(stream)
+    if offset > size {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!("Offset {offset} is past the end of the resource of {size} bytes"),
+        ));
+    }
+
+    let end = if length == 0 {
+        size
+    } else {
+        offset.saturating_add(length).min(size)
+    };
+
+    Ok(offset..end)
 }
 
+/// Returns an entity tag which changes whenever the data of a resource changes.
+pub fn etag(desc: &ResourceDescriptor) -> String {
+    if desc.digest.is_empty() {
+        // Resources written before blob storage have no digest
+        format!("{}-{}", desc.meta.size, desc.meta.timestamp.millis)
+    } else {
+        desc.digest.clone()
+    }
+}
+
+/// Writes data into the blob store, deduplicating it by its SHA-256 digest.
+///
+/// The returned digest is what a [`ResourceDescriptor`] references. If `expected_size` is given,
+/// the written data must have exactly that many bytes.
 pub async fn write(
-    id: ResourceId,
+    database: &Database,
+    expected_size: Option<u64>,
     stream: impl Stream<Item = Result<Vec<u8>, Error>>,
-) -> Result<(), Error> {
-    let path = build_path(&id);
+) -> Result<Written, Error> {
+    let temp = build_temp_path();
+
+    let written = match write_temp(&temp, expected_size, stream).await {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(&temp).await;
+            return Err(err);
+        }
+    };
 
-    fs::create_dir_all(path.parent().expect("Failed to get parent directory"))
-        .await
-        .expect("Failed to create directory");
+    store(database, &temp, &written).await?;
+
+    Ok(written)
+}
+
+/// Copies the file of a built-in resource, or one written before blob storage, into the blob
+/// store.
+pub async fn import(database: &Database, resource_id: &ResourceId) -> Result<Written, Error> {
+    let path = build_path(resource_id);
+    let temp = build_temp_path();
+
+    fs::copy(&path, &temp).await.map_err(|e| {
+        tracing::error!("Failed copying file into blob store: {e}");
+        Error::new(ErrorCode::Internal, "Failed to write resource")
+    })?;
+
+    let written = inspect_file(&temp).await?;
+
+    store(database, &temp, &written).await?;
+
+    Ok(written)
+}
+
+/// Returns the blob of a built-in resource, importing its file into the blob store on first use.
+///
+/// The built-in resource holds a reference to its blob, so the blob is never swept and is only
+/// referenced by further resources afterwards.
+pub async fn builtin_blob(database: &Database, resource_id: &ResourceId) -> Result<Written, Error> {
+    let builtin: Option<Written> = database
+        .select(("builtin_blob", construct_id(resource_id)))
+        .await?;
+
+    if let Some(written) = builtin {
+        return Ok(written);
+    }
+
+    let written = import(database, resource_id).await?;
+
+    database
+        .query(
+            r#"
+BEGIN TRANSACTION;
+UPSERT type::record('builtin_blob', $id) CONTENT $written;
+UPDATE type::record('blob', $written.digest) SET refs += 1, touched_at = $now;
+COMMIT TRANSACTION;
+"#,
+        )
+        .bind(("id", construct_id(resource_id)))
+        .bind(("written", written.clone()))
+        .bind(("now", utils::get_timestamp()))
+        .await?
+        .check()?;
+
+    Ok(written)
+}
+
+/// Moves a fully written file into the blob store, or drops it if the blob already exists.
+pub async fn store(database: &Database, temp: &Path, written: &Written) -> Result<(), Error> {
+    let store = store::get();
+    let key = build_blob_key(&written.digest);
+
+    // Touched before checking for the blob, so the sweep cannot remove it in between. The blob
+    // stays unreferenced until registered and is swept after the grace period if never.
+    database
+        .query(
+            r#"
+UPSERT type::record('blob', $digest)
+SET digest = $digest, size = $size, refs = refs ?? 0, touched_at = $now;
+"#,
+        )
+        .bind(("digest", written.digest.clone()))
+        .bind(("size", written.size))
+        .bind(("now", utils::get_timestamp()))
+        .await?
+        .check()?;
+
+    let result = match store.exists(&key).await {
+        Ok(true) => fs::remove_file(temp).await.map_err(|e| {
+            tracing::error!("Failed removing duplicate blob file: {e}");
+            Error::new(ErrorCode::Internal, "Failed to write resource")
+        }),
+        Ok(false) => store.put_file(&key, temp).await,
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(temp).await;
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+async fn write_temp(
+    path: &Path,
+    expected_size: Option<u64>,
+    stream: impl Stream<Item = Result<Vec<u8>, Error>>,
+) -> Result<Written, Error> {
+    let max_size = config::get().service_max_resource_size;
 
     let file = fs::File::create(path).await.map_err(|e| {
         tracing::error!("Failed opening write file: {e}");
@@ -153,20 +704,100 @@ pub async fn write(
     tokio::pin!(stream);
 
     let mut buf = BufWriter::with_capacity(RESOURCE_CHUNK_SIZE, file);
+    let mut hasher = Sha256::new();
+    let mut prefix = Vec::with_capacity(SNIFF_LENGTH);
+    let mut size = 0;
 
     while let Some(data) = stream.next().await {
-        buf.write(&data?).await.map_err(|e| {
+        let data = data?;
+
+        size += data.len() as u64;
+
+        if size > max_size {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                format!("Resource exceeds the maximum size of {max_size} bytes"),
+            ));
+        }
+
+        if let Some(expected) = expected_size
+            && size > expected
+        {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                "Resource is larger than its declared size",
+            ));
+        }
+
+        hasher.update(&data);
+        fill_prefix(&mut prefix, &data);
+
+        buf.write_all(&data).await.map_err(|e| {
             tracing::error!("Failed writing file: {e}");
             Error::new(ErrorCode::Internal, "Failed to write resource")
         })?;
     }
 
+    if let Some(expected) = expected_size
+        && size != expected
+    {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Resource is smaller than its declared size",
+        ));
+    }
+
     buf.flush().await.map_err(|e| {
         tracing::error!("Failed flushing file: {e}");
         Error::new(ErrorCode::Internal, "Failed to write resource")
     })?;
 
-    Ok(())
+    buf.into_inner().sync_all().await.map_err(|e| {
+        tracing::error!("Failed syncing file: {e}");
+        Error::new(ErrorCode::Internal, "Failed to write resource")
+    })?;
+
+    Ok(Written {
+        size,
+        digest: hex::encode(hasher.finalize()),
+        content_type: sniff(&prefix),
+    })
+}
+
+/// Computes size, SHA-256 digest and content type of a file.
+pub async fn inspect_file(path: &Path) -> Result<Written, Error> {
+    let file = fs::File::open(path).await.map_err(|e| {
+        tracing::error!("Failed opening file for digest: {e}");
+        Error::new(ErrorCode::Internal, "Failed to read resource")
+    })?;
+
+    let mut stream = ReaderStream::with_capacity(file, RESOURCE_CHUNK_SIZE);
+    let mut hasher = Sha256::new();
+    let mut prefix = Vec::with_capacity(SNIFF_LENGTH);
+    let mut size = 0;
+
+    while let Some(data) = stream.next().await {
+        let data = data.map_err(|e| {
+            tracing::error!("Failed reading file for digest: {e}");
+            Error::new(ErrorCode::Internal, "Failed to read resource")
+        })?;
+
+        size += data.len() as u64;
+        hasher.update(&data);
+        fill_prefix(&mut prefix, &data);
+    }
+
+    Ok(Written {
+        size,
+        digest: hex::encode(hasher.finalize()),
+        content_type: sniff(&prefix),
+    })
+}
+
+/// Appends data to the prefix used for content type detection until it is long enough.
+fn fill_prefix(prefix: &mut Vec<u8>, data: &[u8]) {
+    let missing = SNIFF_LENGTH.saturating_sub(prefix.len()).min(data.len());
+    prefix.extend_from_slice(&data[..missing]);
 }
 
 pub fn from_builtin(id: &ResourceId) -> Option<PathBuf> {
@@ -195,6 +826,16 @@ pub fn build_user_avatar_id(user: &str) -> ResourceId {
     }
 }
 
+/// Builds the local path data is written to before being moved into the blob store.
+fn build_temp_path() -> PathBuf {
+    Path::new(&config::get().service_staging_dir).join(format!("~{}", nanoid::nanoid!()))
+}
+
+/// Builds the store key of a blob, spread over prefixes by the first byte of the digest.
+fn build_blob_key(digest: &str) -> String {
+    format!("{}/{digest}", digest.get(..2).unwrap_or("00"))
+}
+
 fn construct_id(id: &ResourceId) -> String {
     format!("{}:{}", id.namespace, id.key)
 }
@@ -210,4 +851,135 @@ pub struct ResourceDescriptor {
     pub resource_id: ResourceId,
     pub meta: ResourceMeta,
     pub user_id: String,
+    /// Hex encoded SHA-256 digest of the resource data, which is also the key of its blob.
+    pub digest: String,
+    /// Content type detected from the resource data, unlike the one declared in the metadata.
+    pub content_type: String,
+}
+
+/// Deduplicated resource data, shared by all resources with the same digest.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Blob {
+    pub digest: String,
+    pub size: u64,
+    /// Number of resources referencing this blob.
+    pub refs: i64,
+    /// Time of the last change of references.
+    pub touched_at: Timestamp,
+}
+
+impl ResourceDescriptor {
+    /// Describes a freshly written resource, taking size, digest and content type from what was
+    /// written.
+    ///
+    /// Fails if the size does not fit into the metadata returned to clients.
+    pub fn written(
+        resource_id: ResourceId,
+        mut meta: ResourceMeta,
+        user_id: String,
+        written: Written,
+    ) -> Result<Self, Error> {
+        meta.size = i32::try_from(written.size)
+            .map_err(|_| Error::new(ErrorCode::InvalidFormat, "Resource is too large"))?;
+        meta.timestamp = utils::get_timestamp();
+        meta.metadata
+            .insert(DIGEST_METADATA_KEY.to_string(), written.digest.clone());
+        meta.metadata.insert(
+            CONTENT_TYPE_METADATA_KEY.to_string(),
+            written.content_type.clone(),
+        );
+
+        Ok(Self {
+            resource_id,
+            meta,
+            user_id,
+            digest: written.digest,
+            content_type: written.content_type,
+        })
+    }
+}
+
+/// Size, digest and detected content type of a written resource file.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Written {
+    pub size: u64,
+    pub digest: String,
+    pub content_type: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sized(size: i32) -> ResourceDescriptor {
+        ResourceDescriptor {
+            resource_id: ResourceId::default(),
+            meta: ResourceMeta {
+                size,
+                ..Default::default()
+            },
+            user_id: String::new(),
+            digest: String::new(),
+            content_type: String::new(),
+        }
+    }
+
+    #[test]
+    fn resolve_range_reads_to_end_without_length() {
+        assert_eq!(resolve_range(&sized(100), 0, 0).unwrap(), 0..100);
+        assert_eq!(resolve_range(&sized(100), 40, 0).unwrap(), 40..100);
+    }
+
+    #[test]
+    fn resolve_range_clamps_to_size() {
+        assert_eq!(resolve_range(&sized(100), 10, 20).unwrap(), 10..30);
+        assert_eq!(resolve_range(&sized(100), 90, 20).unwrap(), 90..100);
+        assert_eq!(resolve_range(&sized(100), 90, u64::MAX).unwrap(), 90..100);
+    }
+
+    #[test]
+    fn resolve_range_allows_empty_range_at_end() {
+        assert_eq!(resolve_range(&sized(100), 100, 0).unwrap(), 100..100);
+        assert_eq!(resolve_range(&sized(0), 0, 10).unwrap(), 0..0);
+    }
+
+    #[test]
+    fn resolve_range_rejects_offset_past_end() {
+        assert_eq!(
+            resolve_range(&sized(100), 101, 0).unwrap_err().code(),
+            ErrorCode::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn sniff_detects_magic_bytes() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"%PDF-1.7\n"), "application/pdf");
+    }
+
+    #[test]
+    fn sniff_detects_text() {
+        assert_eq!(sniff(b"plain text"), TEXT_CONTENT_TYPE);
+        // A character cut off by the end of the prefix
+        assert_eq!(
+            sniff("caf\u{e9}".as_bytes().split_last().unwrap().1),
+            TEXT_CONTENT_TYPE
+        );
+    }
+
+    #[test]
+    fn sniff_falls_back_to_binary() {
+        assert_eq!(sniff(b""), DEFAULT_CONTENT_TYPE);
+        assert_eq!(sniff(b"a\xffb"), DEFAULT_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn matches_content_type_lists() {
+        assert!(matches_content_type("image/png, text/plain", "text/plain"));
+        assert!(matches_content_type("IMAGE/*", "image/webp"));
+        assert!(!matches_content_type("image/*", "application/pdf"));
+        assert!(!matches_content_type("image/png", "image/pngx"));
+        assert!(!matches_content_type("", "text/plain"));
+        assert!(!matches_content_type(" , ", "text/plain"));
+    }
 }
@@ -1,17 +1,51 @@
-use crate::error::Error;
+use crate::error::{self, Error};
+use crate::events::ChannelEvent;
 use crate::state::ServerState;
-use crate::{auth, chat, config, utils};
+use crate::utils::VecStream;
+use crate::{auth, bot, chat, config, export, filter, moderation, resource, user, utils, webhook};
 use elysium_rust::chat::v1::chat_service_server::ChatService;
 use elysium_rust::chat::v1::{
-    ChannelPermission, CreateChannelRequest, CreateChannelResponse, DeleteMessageRequest,
-    DeleteMessageResponse, ReadMessagesRequest, ReadMessagesResponse, SendMessageRequest,
-    SendMessageResponse, UpdateMessageRequest, UpdateMessageResponse, create_channel_response,
-    send_message_response, update_message_response,
+    BotCommand, CancelScheduledMessageRequest, CancelScheduledMessageResponse, ChannelPermission,
+    ChannelSettings, ConnectBotRequest, ConnectBotResponse, CreateChannelRequest,
+    CreateChannelResponse, CreateIncomingWebhookRequest, CreateIncomingWebhookResponse,
+    CreateInviteRequest, CreateInviteResponse, CreateWebhookRequest, CreateWebhookResponse,
+    DeleteIncomingWebhookRequest, DeleteIncomingWebhookResponse, DeleteMessageRequest,
+    DeleteMessageResponse, DeleteWebhookRequest, DeleteWebhookResponse, ExportChannelRequest,
+    ExportChannelResponse, GetChannelSettingsRequest, GetChannelSettingsResponse,
+    GetUnreadCountsRequest, GetUnreadCountsResponse, IncomingWebhook, Invite, JoinChannelRequest,
+    JoinChannelResponse, ListIncomingWebhooksRequest, ListIncomingWebhooksResponse,
+    ListInvitesRequest, ListInvitesResponse, ListPinnedMessagesRequest, ListPinnedMessagesResponse,
+    ListScheduledMessagesRequest, ListScheduledMessagesResponse, ListWebhookDeliveriesRequest,
+    ListWebhookDeliveriesResponse, ListWebhooksRequest, ListWebhooksResponse, MarkReadRequest,
+    MarkReadResponse, MemberChanged, MessageDeleted, MessagePinned, MessageUnpinned,
+    PinMessageRequest, PinMessageResponse, PostWebhookMessageRequest, PostWebhookMessageResponse,
+    ReadDirection, ReadMessagesRequest, ReadMessagesResponse, ReplyCommandRequest,
+    ReplyCommandResponse, RevokeInviteRequest, RevokeInviteResponse, ScheduleMessageRequest,
+    ScheduleMessageResponse, ScheduledMessage, SendMessageRequest, SendMessageResponse,
+    SubscribeRequest, SubscribeResponse, UnpinMessageRequest, UnpinMessageResponse, UnreadCount,
+    UpdateChannelSettingsRequest, UpdateChannelSettingsResponse, UpdateMessageRequest,
+    UpdateMessageResponse, UpdateScheduledMessageRequest, UpdateScheduledMessageResponse, Webhook,
+    WebhookDelivery, channel_event, connect_bot_response, create_channel_response,
+    create_incoming_webhook_response, create_invite_response, create_webhook_response,
+    export_channel_response, get_channel_settings_response, join_channel_response,
+    post_webhook_message_response, reply_command_response, schedule_message_response,
+    send_message_response, subscribe_response, update_message_response,
+    update_scheduled_message_response,
 };
 use elysium_rust::common::v1::ErrorCode;
-use elysium_rust::{Channel, Content, Message, Timestamp};
+use elysium_rust::user::v1::UserRole;
+use elysium_rust::{Channel, Content, Message, Timestamp, User};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tonic::codegen::BoxStream;
+use tonic::codegen::tokio_stream::StreamExt;
 use tonic::{Request, Response, Status};
 
+/// Number of export progress updates buffered for slow clients.
+const EXPORT_PROGRESS_BUFFER_SIZE: usize = 16;
+
 pub struct Service {
     state: ServerState,
 }
@@ -65,17 +99,40 @@ impl Service {
             return Err(Error::new(ErrorCode::Unauthorized, "User not in channel"));
         }
 
-        let messages = chat::read_messages(
+        let direction = match ReadDirection::try_from(msg_args.direction)
+            .map_err(|_| Error::invalid_argument())?
+        {
+            ReadDirection::Backward => chat::Direction::Backward,
+            ReadDirection::Forward => chat::Direction::Forward,
+        };
+
+        let cursor = if !msg_args.cursor.is_empty() {
+            chat::Cursor::parse(&msg_args.cursor)?
+        } else if let Some(start_time) = msg_args.start_time {
+            chat::Cursor {
+                created_at: Timestamp::try_from(start_time)?.millis,
+                message_id: String::new(),
+            }
+        } else {
+            match direction {
+                chat::Direction::Backward => chat::Cursor::end(),
+                chat::Direction::Forward => chat::Cursor::start(),
+            }
+        };
+
+        let (messages, next) = chat::read_messages(
             database,
             msg_args.channel_id,
             msg_args.limit,
-            Timestamp::try_from(msg_args.start_time.ok_or(Error::invalid_argument())?)?,
+            cursor,
+            direction,
         )
         .await?;
 
         Ok(ReadMessagesResponse {
             error: None,
             messages: messages.into_iter().map(|m| m.into()).collect(),
+            next_cursor: next.map(|cursor| cursor.to_string()).unwrap_or_default(),
         })
     }
 
@@ -88,26 +145,67 @@ impl Service {
         let user = auth::verify(database, &request).await?;
         let msg_args = request.into_inner();
 
-        let mut content = msg_args.content.ok_or(Error::invalid_argument())?;
+        let mut content = Content::try_from(msg_args.content.ok_or(Error::invalid_argument())?)?;
 
-        content.created_at = Some(utils::get_timestamp().into());
+        content.created_at = utils::get_timestamp();
 
         let perm =
             chat::get_channel_member_perm(database, &msg_args.channel_id, &user.user_id).await?;
 
-        if perm == ChannelPermission::ReadWrite || perm == ChannelPermission::Manager {
-            let id = chat::build_message_id(database).await?;
-            let msg = chat::send(
+        if chat::can_send(perm) {
+            moderation::check_can_post(database, &msg_args.channel_id, &user.user_id).await?;
+
+            resource::validate_attachments(
                 database,
-                Message {
-                    message_id: id,
-                    user_id: user.user_id,
-                    channel_id: msg_args.channel_id,
-                    content: content.try_into()?,
-                },
+                &msg_args.channel_id,
+                &user.user_id,
+                &content.attachments,
             )
             .await?;
 
+            let msg = Message {
+                message_id: chat::build_message_id(),
+                user_id: user.user_id,
+                channel_id: msg_args.channel_id,
+                content,
+            };
+
+            // Slash commands handled by a bot in the channel are routed to the bot instead, and
+            // posted like any other message while the bot is offline
+            if let Some((command, args)) = bot::parse_command(&msg.content.text) {
+                let channel = chat::get_channel(database, &msg.channel_id)
+                    .await?
+                    .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+                if let Some(bot) =
+                    bot::find_command_bot(database, channel.members.keys(), command).await?
+                    && self.state.bots().is_connected(&bot.user_id)
+                {
+                    // Commands are not stored, so they are only rejected or masked
+                    let mut content = msg.content.clone();
+                    self.state.filters().apply(&msg.channel_id, &mut content)?;
+
+                    chat::check_slow_mode(&self.state, &msg.channel_id, &msg.user_id).await?;
+
+                    self.state.bots().dispatch(bot::Command {
+                        command_id: msg.message_id.clone(),
+                        bot_id: bot.user_id,
+                        channel_id: msg.channel_id.clone(),
+                        user_id: msg.user_id.clone(),
+                        command: command.to_string(),
+                        args: args.to_string(),
+                        issued_at: content.created_at.clone(),
+                        content,
+                    })?;
+
+                    return Ok(SendMessageResponse {
+                        result: Some(send_message_response::Result::Message(msg.into())),
+                    });
+                }
+            }
+
+            let msg = chat::post(&self.state, msg, msg_args.ttl).await?;
+
             Ok(SendMessageResponse {
                 result: Some(send_message_response::Result::Message(msg.into())),
             })
@@ -119,6 +217,158 @@ impl Service {
         }
     }
 
+    async fn _schedule_message(
+        &self,
+        request: Request<ScheduleMessageRequest>,
+    ) -> Result<ScheduleMessageResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let content = Content::try_from(args.content.ok_or(Error::invalid_argument())?)?;
+        let send_at = Timestamp::try_from(args.send_at.ok_or(Error::invalid_argument())?)?;
+
+        if send_at.millis <= utils::get_timestamp().millis {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                "Scheduled time must be in the future",
+            ));
+        }
+
+        let perm = chat::get_channel_member_perm(database, &args.channel_id, &user.user_id).await?;
+
+        if !chat::can_send(perm) {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to send messages",
+            ));
+        }
+
+        resource::validate_attachments(
+            database,
+            &args.channel_id,
+            &user.user_id,
+            &content.attachments,
+        )
+        .await?;
+
+        let scheduled = chat::schedule_message(
+            database,
+            chat::ScheduledMessage {
+                schedule_id: chat::build_schedule_id(),
+                user_id: user.user_id,
+                channel_id: args.channel_id,
+                content,
+                send_at,
+                ttl: args.ttl,
+                attempts: 0,
+            },
+        )
+        .await?;
+
+        Ok(ScheduleMessageResponse {
+            result: Some(schedule_message_response::Result::Scheduled(
+                to_proto_scheduled(scheduled),
+            )),
+        })
+    }
+
+    async fn _list_scheduled_messages(
+        &self,
+        request: Request<ListScheduledMessagesRequest>,
+    ) -> Result<ListScheduledMessagesResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+
+        let scheduled = chat::get_user_scheduled(database, &user.user_id).await?;
+
+        Ok(ListScheduledMessagesResponse {
+            scheduled: scheduled.into_iter().map(to_proto_scheduled).collect(),
+            error: None,
+        })
+    }
+
+    async fn _update_scheduled_message(
+        &self,
+        request: Request<UpdateScheduledMessageRequest>,
+    ) -> Result<UpdateScheduledMessageResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let mut scheduled = chat::get_scheduled(database, &args.schedule_id)
+            .await?
+            .filter(|scheduled| scheduled.user_id == user.user_id)
+            .ok_or(Error::new(
+                ErrorCode::NotFound,
+                "Scheduled message not found",
+            ))?;
+
+        if let Some(content) = args.content {
+            let content = Content::try_from(content)?;
+
+            resource::validate_attachments(
+                database,
+                &scheduled.channel_id,
+                &user.user_id,
+                &content.attachments,
+            )
+            .await?;
+
+            scheduled.content = content;
+        }
+
+        if let Some(send_at) = args.send_at {
+            let send_at = Timestamp::try_from(send_at)?;
+
+            if send_at.millis <= utils::get_timestamp().millis {
+                return Err(Error::new(
+                    ErrorCode::InvalidFormat,
+                    "Scheduled time must be in the future",
+                ));
+            }
+
+            scheduled.send_at = send_at;
+        }
+
+        if let Some(ttl) = args.ttl {
+            scheduled.ttl = ttl;
+        }
+
+        let scheduled = chat::update_scheduled(database, scheduled).await?;
+
+        Ok(UpdateScheduledMessageResponse {
+            result: Some(update_scheduled_message_response::Result::Scheduled(
+                to_proto_scheduled(scheduled),
+            )),
+        })
+    }
+
+    async fn _cancel_scheduled_message(
+        &self,
+        request: Request<CancelScheduledMessageRequest>,
+    ) -> Result<CancelScheduledMessageResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let schedule_id = request.into_inner().schedule_id;
+
+        chat::get_scheduled(database, &schedule_id)
+            .await?
+            .filter(|scheduled| scheduled.user_id == user.user_id)
+            .ok_or(Error::new(
+                ErrorCode::NotFound,
+                "Scheduled message not found",
+            ))?;
+
+        chat::cancel_scheduled(database, &schedule_id).await?;
+
+        Ok(CancelScheduledMessageResponse { error: None })
+    }
+
     async fn _delete_message(
         &self,
         request: Request<DeleteMessageRequest>,
@@ -138,7 +388,17 @@ impl Service {
             || (perm == ChannelPermission::ReadWrite && message.user_id == user.user_id))
             && user.role >= config.service_allow_message_delete
         {
-            chat::delete_message(database, &message.message_id).await?;
+            if let Some(pin) = chat::delete_message(database, &message.message_id).await? {
+                self.state.events().publish(ChannelEvent::MessageUnpinned {
+                    channel_id: pin.channel_id,
+                    message_id: pin.message_id,
+                });
+            }
+
+            self.state.events().publish(ChannelEvent::MessageDeleted {
+                channel_id: message.channel_id,
+                message_id: message.message_id,
+            });
 
             Ok(DeleteMessageResponse { error: None })
         } else {
@@ -170,10 +430,30 @@ impl Service {
             || (perm == ChannelPermission::ReadWrite && message.user_id == user.user_id))
             && user.role >= config.service_allow_message_update
         {
-            content.created_at = utils::get_timestamp();
+            // Attachments the message already had were validated when they were added
+            let added: Vec<_> = content
+                .attachments
+                .iter()
+                .filter(|attachment| !message.content.attachments.contains(attachment))
+                .cloned()
+                .collect();
+
+            resource::validate_attachments(database, &message.channel_id, &user.user_id, &added)
+                .await?;
+
+            let flagged = self
+                .state
+                .filters()
+                .apply(&message.channel_id, &mut content)?;
 
             let message = chat::update_message(database, &message.message_id, content).await?;
 
+            filter::flag(database, &message, &flagged).await?;
+
+            self.state
+                .events()
+                .publish(ChannelEvent::MessageUpdated(message.clone()));
+
             Ok(UpdateMessageResponse {
                 result: Some(update_message_response::Result::Message(message.into())),
             })
@@ -184,78 +464,1403 @@ impl Service {
             ))
         }
     }
-}
 
-#[tonic::async_trait]
-impl ChatService for Service {
-    async fn create_channel(
+    async fn _pin_message(
         &self,
-        request: Request<CreateChannelRequest>,
-    ) -> Result<Response<CreateChannelResponse>, Status> {
-        let resp =
-            self._create_channel(request)
-                .await
-                .unwrap_or_else(|err| CreateChannelResponse {
-                    result: Some(create_channel_response::Result::Error(err.into())),
-                });
+        request: Request<PinMessageRequest>,
+    ) -> Result<PinMessageResponse, Error> {
+        let database = self.state.database();
 
-        Ok(Response::new(resp))
+        let user = auth::verify(database, &request).await?;
+        let message = chat::get_msg(database, &request.into_inner().message_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Message not found"))?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &message.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to pin messages",
+            ));
+        }
+
+        let pin = chat::pin_message(
+            database,
+            chat::Pin {
+                channel_id: message.channel_id,
+                message_id: message.message_id,
+                user_id: user.user_id,
+                pinned_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        self.state
+            .events()
+            .publish(ChannelEvent::MessagePinned(pin));
+
+        Ok(PinMessageResponse { error: None })
     }
 
-    async fn read_messages(
+    async fn _unpin_message(
         &self,
-        request: Request<ReadMessagesRequest>,
-    ) -> Result<Response<ReadMessagesResponse>, Status> {
-        let resp = self
-            ._read_messages(request)
-            .await
-            .unwrap_or_else(|err| ReadMessagesResponse {
-                messages: Vec::new(),
-                error: Some(err.into()),
-            });
+        request: Request<UnpinMessageRequest>,
+    ) -> Result<UnpinMessageResponse, Error> {
+        let database = self.state.database();
 
-        Ok(Response::new(resp))
+        let user = auth::verify(database, &request).await?;
+        let pin = chat::get_pin(database, &request.into_inner().message_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Message is not pinned"))?;
+
+        let perm = chat::get_channel_member_perm(database, &pin.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to unpin messages",
+            ));
+        }
+
+        chat::unpin_message(database, &pin.message_id).await?;
+
+        self.state.events().publish(ChannelEvent::MessageUnpinned {
+            channel_id: pin.channel_id,
+            message_id: pin.message_id,
+        });
+
+        Ok(UnpinMessageResponse { error: None })
     }
 
-    async fn send_message(
+    async fn _list_pinned_messages(
         &self,
-        request: Request<SendMessageRequest>,
-    ) -> Result<Response<SendMessageResponse>, Status> {
-        let resp = self
-            ._send_message(request)
-            .await
-            .unwrap_or_else(|err| SendMessageResponse {
-                result: Some(send_message_response::Result::Error(err.into())),
-            });
+        request: Request<ListPinnedMessagesRequest>,
+    ) -> Result<ListPinnedMessagesResponse, Error> {
+        let database = self.state.database();
 
-        Ok(Response::new(resp))
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        let messages = chat::get_pinned_messages(database, channel_id).await?;
+
+        Ok(ListPinnedMessagesResponse {
+            messages: messages.into_iter().map(|m| m.into()).collect(),
+            error: None,
+        })
     }
 
-    async fn delete_message(
+    async fn _subscribe(
         &self,
-        request: Request<DeleteMessageRequest>,
-    ) -> Result<Response<DeleteMessageResponse>, Status> {
-        let resp =
-            self._delete_message(request)
-                .await
-                .unwrap_or_else(|err| DeleteMessageResponse {
-                    error: Some(err.into()),
-                });
+        request: Request<SubscribeRequest>,
+    ) -> Result<BoxStream<SubscribeResponse>, Error> {
+        let database = self.state.database();
 
-        Ok(Response::new(resp))
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        let user_id = user.user_id;
+        let stream = BroadcastStream::new(self.state.events().subscribe(&channel_id)).filter_map(
+            move |res| {
+                let result = match res {
+                    Ok(ChannelEvent::Ephemeral { recipient, .. }) if recipient != user_id => {
+                        return None;
+                    }
+                    Ok(event) => subscribe_response::Result::Event(to_proto_event(event)),
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        subscribe_response::Result::Error(
+                            Error::new(
+                                ErrorCode::Internal,
+                                format!("Missed {missed} channel events"),
+                            )
+                            .into(),
+                        )
+                    }
+                };
+
+                Some(Ok(SubscribeResponse {
+                    result: Some(result),
+                }))
+            },
+        );
+
+        Ok(Box::pin(stream))
     }
 
-    async fn update_message(
+    async fn _get_channel_settings(
         &self,
-        request: Request<UpdateMessageRequest>,
-    ) -> Result<Response<UpdateMessageResponse>, Status> {
-        let resp =
-            self._update_message(request)
-                .await
-                .unwrap_or_else(|err| UpdateMessageResponse {
-                    result: Some(update_message_response::Result::Error(err.into())),
-                });
+        request: Request<GetChannelSettingsRequest>,
+    ) -> Result<GetChannelSettingsResponse, Error> {
+        let database = self.state.database();
 
-        Ok(Response::new(resp))
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        let settings = chat::get_channel_settings(database, &channel_id).await?;
+
+        Ok(GetChannelSettingsResponse {
+            result: Some(get_channel_settings_response::Result::Settings(
+                ChannelSettings {
+                    channel_id: settings.channel_id,
+                    message_ttl: settings.message_ttl,
+                    slow_mode: settings.slow_mode,
+                },
+            )),
+        })
+    }
+
+    async fn _update_channel_settings(
+        &self,
+        request: Request<UpdateChannelSettingsRequest>,
+    ) -> Result<UpdateChannelSettingsResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let settings = request
+            .into_inner()
+            .settings
+            .ok_or(Error::invalid_argument())?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &settings.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to change channel settings",
+            ));
+        }
+
+        chat::update_channel_settings(
+            database,
+            chat::ChannelSettings {
+                channel_id: settings.channel_id,
+                message_ttl: settings.message_ttl,
+                slow_mode: settings.slow_mode,
+            },
+        )
+        .await?;
+
+        Ok(UpdateChannelSettingsResponse { error: None })
+    }
+
+    async fn _create_webhook(
+        &self,
+        request: Request<CreateWebhookRequest>,
+    ) -> Result<CreateWebhookResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let perm = chat::get_channel_member_perm(database, &args.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        let webhook = webhook::create(
+            database,
+            webhook::Webhook {
+                webhook_id: webhook::build_id(),
+                channel_id: args.channel_id,
+                url: args.url,
+                secret: webhook::build_secret(),
+                events: args.events,
+                user_id: user.user_id,
+                created_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        // The secret is only revealed once, on creation
+        Ok(CreateWebhookResponse {
+            result: Some(create_webhook_response::Result::Webhook(to_proto_webhook(
+                webhook, true,
+            ))),
+        })
+    }
+
+    async fn _list_webhooks(
+        &self,
+        request: Request<ListWebhooksRequest>,
+    ) -> Result<ListWebhooksResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        let perm = chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        let webhooks = webhook::get_channel_webhooks(database, &channel_id).await?;
+
+        Ok(ListWebhooksResponse {
+            webhooks: webhooks
+                .into_iter()
+                .map(|webhook| to_proto_webhook(webhook, false))
+                .collect(),
+            error: None,
+        })
+    }
+
+    async fn _delete_webhook(
+        &self,
+        request: Request<DeleteWebhookRequest>,
+    ) -> Result<DeleteWebhookResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let webhook = webhook::get(database, &request.into_inner().webhook_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Webhook not found"))?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &webhook.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        webhook::delete(database, &webhook.webhook_id).await?;
+
+        Ok(DeleteWebhookResponse { error: None })
+    }
+
+    async fn _list_webhook_deliveries(
+        &self,
+        request: Request<ListWebhookDeliveriesRequest>,
+    ) -> Result<ListWebhookDeliveriesResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+        let webhook = webhook::get(database, &args.webhook_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Webhook not found"))?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &webhook.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        let deliveries = webhook::get_deliveries(database, &webhook.webhook_id, args.limit).await?;
+
+        Ok(ListWebhookDeliveriesResponse {
+            deliveries: deliveries
+                .into_iter()
+                .map(|delivery| WebhookDelivery {
+                    delivery_id: delivery.delivery_id,
+                    event: delivery.event,
+                    status: delivery.status,
+                    attempts: delivery.attempts,
+                    last_error: delivery.last_error,
+                    created_at: Some(delivery.created_at.into()),
+                    next_attempt: Some(delivery.next_attempt.into()),
+                })
+                .collect(),
+            error: None,
+        })
+    }
+
+    async fn _create_incoming_webhook(
+        &self,
+        request: Request<CreateIncomingWebhookRequest>,
+    ) -> Result<CreateIncomingWebhookResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let perm = chat::get_channel_member_perm(database, &args.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        let webhook_id = webhook::build_id();
+        let secret = webhook::build_secret();
+        let bot_id = webhook::build_incoming_user_id(&webhook_id);
+
+        // Messages are attributed to a bot user, which can never log in with a password
+        user::create(
+            database,
+            User {
+                user_id: bot_id.clone(),
+                username: args.name.clone(),
+                email: String::new(),
+                password: String::new(),
+                role: UserRole::UserUnspecified as i32,
+                icon: resource::build_user_avatar_id(&bot_id),
+            },
+        )
+        .await?;
+
+        let incoming = webhook::create_incoming(
+            database,
+            webhook::IncomingWebhook {
+                webhook_id: webhook_id.clone(),
+                channel_id: args.channel_id,
+                name: args.name,
+                secret_hash: utils::hash_token(&secret),
+                user_id: user.user_id,
+                created_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        // The token is only revealed once, on creation
+        let mut incoming = to_proto_incoming(incoming);
+        incoming.token = format!("{webhook_id}.{secret}");
+
+        Ok(CreateIncomingWebhookResponse {
+            result: Some(create_incoming_webhook_response::Result::Webhook(incoming)),
+        })
+    }
+
+    async fn _list_incoming_webhooks(
+        &self,
+        request: Request<ListIncomingWebhooksRequest>,
+    ) -> Result<ListIncomingWebhooksResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        let perm = chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        let webhooks = webhook::get_channel_incoming(database, &channel_id).await?;
+
+        Ok(ListIncomingWebhooksResponse {
+            webhooks: webhooks.into_iter().map(to_proto_incoming).collect(),
+            error: None,
+        })
+    }
+
+    async fn _delete_incoming_webhook(
+        &self,
+        request: Request<DeleteIncomingWebhookRequest>,
+    ) -> Result<DeleteIncomingWebhookResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let incoming = webhook::get_incoming(database, &request.into_inner().webhook_id)
+            .await?
+            .ok_or(Error::new(
+                ErrorCode::NotFound,
+                "Incoming webhook not found",
+            ))?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &incoming.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to manage webhooks",
+            ));
+        }
+
+        webhook::delete_incoming(database, &incoming.webhook_id).await?;
+        user::delete(
+            database,
+            &webhook::build_incoming_user_id(&incoming.webhook_id),
+        )
+        .await?;
+
+        Ok(DeleteIncomingWebhookResponse { error: None })
+    }
+
+    async fn _post_webhook_message(
+        &self,
+        request: Request<PostWebhookMessageRequest>,
+    ) -> Result<PostWebhookMessageResponse, Error> {
+        let config = config::get();
+        let database = self.state.database();
+
+        let args = request.into_inner();
+        let incoming = webhook::verify_incoming(database, &args.token).await?;
+
+        self.state
+            .rate_limiter()
+            .check(
+                &format!("incoming_webhook:{}", incoming.webhook_id),
+                config.service_webhook_rate_limit,
+                Duration::from_secs(60),
+            )
+            .map_err(Error::rate_limited)?;
+
+        if args.text.trim().is_empty() {
+            return Err(Error::invalid_argument());
+        }
+
+        let user_id = webhook::build_incoming_user_id(&incoming.webhook_id);
+
+        moderation::check_can_post(database, &incoming.channel_id, &user_id).await?;
+
+        let msg = chat::post(
+            &self.state,
+            Message {
+                message_id: chat::build_message_id(),
+                user_id,
+                channel_id: incoming.channel_id,
+                content: Content {
+                    text: args.text,
+                    attachments: Vec::new(),
+                    created_at: utils::get_timestamp(),
+                },
+            },
+            0,
+        )
+        .await?;
+
+        Ok(PostWebhookMessageResponse {
+            result: Some(post_webhook_message_response::Result::Message(msg.into())),
+        })
+    }
+
+    async fn _create_invite(
+        &self,
+        request: Request<CreateInviteRequest>,
+    ) -> Result<CreateInviteResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let perm = chat::get_channel_member_perm(database, &args.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to create invites",
+            ));
+        }
+
+        let permission =
+            ChannelPermission::try_from(args.permission).map_err(|_| Error::invalid_argument())?;
+
+        let now = utils::get_timestamp();
+        let expires_at = if args.expires_in == 0 {
+            Timestamp { millis: 0 }
+        } else {
+            Timestamp {
+                millis: now.millis + args.expires_in * 1000,
+            }
+        };
+
+        let invite = chat::create_invite(
+            database,
+            chat::Invite {
+                code: chat::build_invite_code(),
+                channel_id: args.channel_id,
+                permission: permission as i32,
+                max_uses: args.max_uses,
+                uses: 0,
+                expires_at,
+                created_by: user.user_id,
+                created_at: now,
+            },
+        )
+        .await?;
+
+        Ok(CreateInviteResponse {
+            result: Some(create_invite_response::Result::Invite(to_proto_invite(
+                invite,
+            ))),
+        })
+    }
+
+    async fn _list_invites(
+        &self,
+        request: Request<ListInvitesRequest>,
+    ) -> Result<ListInvitesResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        let perm = chat::get_channel_member_perm(database, &channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to list invites",
+            ));
+        }
+
+        let invites = chat::get_channel_invites(database, &channel_id).await?;
+
+        Ok(ListInvitesResponse {
+            invites: invites.into_iter().map(to_proto_invite).collect(),
+            error: None,
+        })
+    }
+
+    async fn _revoke_invite(
+        &self,
+        request: Request<RevokeInviteRequest>,
+    ) -> Result<RevokeInviteResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let invite = chat::get_invite(database, &request.into_inner().code)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Invite not found"))?;
+
+        let perm =
+            chat::get_channel_member_perm(database, &invite.channel_id, &user.user_id).await?;
+
+        if perm != ChannelPermission::Manager {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to revoke invites",
+            ));
+        }
+
+        chat::revoke_invite(database, &invite.code).await?;
+
+        Ok(RevokeInviteResponse { error: None })
+    }
+
+    async fn _join_channel(
+        &self,
+        request: Request<JoinChannelRequest>,
+    ) -> Result<JoinChannelResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let code = request.into_inner().code;
+
+        let invite = chat::get_invite(database, &code)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Invite not found"))?;
+
+        let channel = chat::get_channel(database, &invite.channel_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+        if channel.members.contains_key(&user.user_id) {
+            return Err(Error::new(
+                ErrorCode::AlreadyExists,
+                "User is already in this channel",
+            ));
+        }
+
+        let invite = chat::use_invite(database, &code, utils::get_timestamp())
+            .await?
+            .ok_or(Error::new(
+                ErrorCode::NotFound,
+                "Invite is used up or expired",
+            ))?;
+
+        let permission = ChannelPermission::try_from(invite.permission)
+            .map_err(|_| Error::new(ErrorCode::Internal, "Failed to parse channel permission"))?;
+
+        let channel =
+            chat::add_member(database, &invite.channel_id, &user.user_id, permission).await?;
+
+        self.state.events().publish(ChannelEvent::MemberChanged {
+            channel_id: channel.channel_id.clone(),
+            user_id: user.user_id,
+            permission,
+        });
+
+        Ok(JoinChannelResponse {
+            result: Some(join_channel_response::Result::Channel(channel.into())),
+        })
+    }
+
+    /// Exports a channel for its managers and admins, see [`export::export_channel`].
+    async fn _export_channel(
+        &self,
+        request: Request<ExportChannelRequest>,
+    ) -> Result<BoxStream<ExportChannelResponse>, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        // Admins need not be members, so the membership is only looked up for everyone else
+        if user.role < UserRole::Admin as i32 {
+            let channel = chat::get_channel(database, &channel_id)
+                .await?
+                .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+            if channel.members.get(&user.user_id).copied()
+                != Some(ChannelPermission::Manager as i32)
+            {
+                return Err(Error::new(
+                    ErrorCode::Unauthorized,
+                    "User has no permission to export this channel",
+                ));
+            }
+        }
+
+        let (tx, rx) = mpsc::channel(EXPORT_PROGRESS_BUFFER_SIZE);
+        let database = database.clone();
+
+        // The export keeps running if the client disconnects, the file can be downloaded later
+        tokio::spawn(async move {
+            let result = export::export_channel(&database, &channel_id, &user.user_id, |count| {
+                // Progress updates are dropped if the client falls behind
+                let _ = tx.try_send(Ok(ExportChannelResponse {
+                    result: Some(export_channel_response::Result::Exported(count)),
+                }));
+            })
+            .await;
+
+            let result = match result {
+                Ok(desc) => export_channel_response::Result::Resource(desc.resource_id.into()),
+                Err(err) => {
+                    tracing::error!("Failed exporting channel '{channel_id}': {err}");
+                    export_channel_response::Result::Error(err.into())
+                }
+            };
+
+            let _ = tx
+                .send(Ok(ExportChannelResponse {
+                    result: Some(result),
+                }))
+                .await;
+        });
+
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+
+    async fn _connect_bot(
+        &self,
+        request: Request<ConnectBotRequest>,
+    ) -> Result<BoxStream<ConnectBotResponse>, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+
+        if !bot::is_bot(database, &user.user_id).await? {
+            return Err(Error::new(ErrorCode::Unauthorized, "User is not a bot"));
+        }
+
+        let stream = BroadcastStream::new(self.state.bots().connect(&user.user_id)).map(|res| {
+            let result = match res {
+                Ok(command) => connect_bot_response::Result::Command(BotCommand {
+                    command_id: command.command_id,
+                    channel_id: command.channel_id,
+                    user_id: command.user_id,
+                    command: command.command,
+                    args: command.args,
+                    content: Some(command.content.into()),
+                }),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    connect_bot_response::Result::Error(
+                        Error::new(ErrorCode::Internal, format!("Missed {missed} commands")).into(),
+                    )
+                }
+            };
+
+            Ok(ConnectBotResponse {
+                result: Some(result),
+            })
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn _reply_command(
+        &self,
+        request: Request<ReplyCommandRequest>,
+    ) -> Result<ReplyCommandResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let command = self
+            .state
+            .bots()
+            .take(&user.user_id, &args.command_id)
+            .ok_or(Error::new(ErrorCode::NotFound, "Command not found"))?;
+
+        let mut content = Content::try_from(args.content.ok_or(Error::invalid_argument())?)?;
+        content.created_at = utils::get_timestamp();
+
+        let mut msg = Message {
+            message_id: chat::build_message_id(),
+            user_id: user.user_id,
+            channel_id: command.channel_id,
+            content,
+        };
+
+        let msg = if args.ephemeral {
+            self.state
+                .filters()
+                .apply(&msg.channel_id, &mut msg.content)?;
+
+            self.state.events().publish(ChannelEvent::Ephemeral {
+                recipient: command.user_id,
+                message: msg.clone(),
+            });
+
+            msg
+        } else {
+            let perm =
+                chat::get_channel_member_perm(database, &msg.channel_id, &msg.user_id).await?;
+
+            if !chat::can_send(perm) {
+                return Err(Error::new(
+                    ErrorCode::Unauthorized,
+                    "User has no permission to send messages",
+                ));
+            }
+
+            moderation::check_can_post(database, &msg.channel_id, &msg.user_id).await?;
+
+            resource::validate_attachments(
+                database,
+                &msg.channel_id,
+                &msg.user_id,
+                &msg.content.attachments,
+            )
+            .await?;
+
+            chat::post(&self.state, msg, 0).await?
+        };
+
+        Ok(ReplyCommandResponse {
+            result: Some(reply_command_response::Result::Message(msg.into())),
+        })
+    }
+
+    async fn _mark_read(
+        &self,
+        request: Request<MarkReadRequest>,
+    ) -> Result<MarkReadResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        chat::get_channel_member_perm(database, &args.channel_id, &user.user_id).await?;
+
+        let message = chat::get_msg(database, &args.message_id)
+            .await?
+            .filter(|message| message.channel_id == args.channel_id)
+            .ok_or(Error::new(ErrorCode::NotFound, "Message not found"))?;
+
+        chat::mark_read(database, &user.user_id, &message).await?;
+
+        Ok(MarkReadResponse { error: None })
+    }
+
+    async fn _get_unread_counts(
+        &self,
+        request: Request<GetUnreadCountsRequest>,
+    ) -> Result<GetUnreadCountsResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+
+        let channel_ids = chat::get_user_channels(database, &user.user_id)
+            .await?
+            .into_iter()
+            .map(|channel| channel.channel_id)
+            .collect();
+
+        let counts = chat::get_read_states(database, channel_ids, &user.user_id)
+            .await?
+            .into_iter()
+            .map(|state| UnreadCount {
+                channel_id: state.channel_id,
+                last_read_message_id: state.message_id,
+                unread: state.unread,
+                mentions: state.mentions,
+            })
+            .collect();
+
+        Ok(GetUnreadCountsResponse {
+            counts,
+            error: None,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl ChatService for Service {
+    async fn create_channel(
+        &self,
+        request: Request<CreateChannelRequest>,
+    ) -> Result<Response<CreateChannelResponse>, Status> {
+        let resp =
+            self._create_channel(request)
+                .await
+                .unwrap_or_else(|err| CreateChannelResponse {
+                    result: Some(create_channel_response::Result::Error(err.into())),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn read_messages(
+        &self,
+        request: Request<ReadMessagesRequest>,
+    ) -> Result<Response<ReadMessagesResponse>, Status> {
+        let resp = self
+            ._read_messages(request)
+            .await
+            .unwrap_or_else(|err| ReadMessagesResponse {
+                messages: Vec::new(),
+                next_cursor: String::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<SendMessageRequest>,
+    ) -> Result<Response<SendMessageResponse>, Status> {
+        Ok(error::respond(self._send_message(request).await, |err| {
+            SendMessageResponse {
+                result: Some(send_message_response::Result::Error(err.into())),
+            }
+        }))
+    }
+
+    async fn delete_message(
+        &self,
+        request: Request<DeleteMessageRequest>,
+    ) -> Result<Response<DeleteMessageResponse>, Status> {
+        let resp =
+            self._delete_message(request)
+                .await
+                .unwrap_or_else(|err| DeleteMessageResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn update_message(
+        &self,
+        request: Request<UpdateMessageRequest>,
+    ) -> Result<Response<UpdateMessageResponse>, Status> {
+        let resp =
+            self._update_message(request)
+                .await
+                .unwrap_or_else(|err| UpdateMessageResponse {
+                    result: Some(update_message_response::Result::Error(err.into())),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn mark_read(
+        &self,
+        request: Request<MarkReadRequest>,
+    ) -> Result<Response<MarkReadResponse>, Status> {
+        let resp = self
+            ._mark_read(request)
+            .await
+            .unwrap_or_else(|err| MarkReadResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn get_unread_counts(
+        &self,
+        request: Request<GetUnreadCountsRequest>,
+    ) -> Result<Response<GetUnreadCountsResponse>, Status> {
+        let resp = self
+            ._get_unread_counts(request)
+            .await
+            .unwrap_or_else(|err| GetUnreadCountsResponse {
+                counts: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn pin_message(
+        &self,
+        request: Request<PinMessageRequest>,
+    ) -> Result<Response<PinMessageResponse>, Status> {
+        let resp = self
+            ._pin_message(request)
+            .await
+            .unwrap_or_else(|err| PinMessageResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn unpin_message(
+        &self,
+        request: Request<UnpinMessageRequest>,
+    ) -> Result<Response<UnpinMessageResponse>, Status> {
+        let resp = self
+            ._unpin_message(request)
+            .await
+            .unwrap_or_else(|err| UnpinMessageResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_pinned_messages(
+        &self,
+        request: Request<ListPinnedMessagesRequest>,
+    ) -> Result<Response<ListPinnedMessagesResponse>, Status> {
+        let resp = self
+            ._list_pinned_messages(request)
+            .await
+            .unwrap_or_else(|err| ListPinnedMessagesResponse {
+                messages: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    type SubscribeStream = BoxStream<SubscribeResponse>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let resp = self._subscribe(request).await.unwrap_or_else(|err| {
+            Box::pin(VecStream::once(Ok(SubscribeResponse {
+                result: Some(subscribe_response::Result::Error(err.into())),
+            })))
+        });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn get_channel_settings(
+        &self,
+        request: Request<GetChannelSettingsRequest>,
+    ) -> Result<Response<GetChannelSettingsResponse>, Status> {
+        let resp = self
+            ._get_channel_settings(request)
+            .await
+            .unwrap_or_else(|err| GetChannelSettingsResponse {
+                result: Some(get_channel_settings_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn update_channel_settings(
+        &self,
+        request: Request<UpdateChannelSettingsRequest>,
+    ) -> Result<Response<UpdateChannelSettingsResponse>, Status> {
+        let resp = self
+            ._update_channel_settings(request)
+            .await
+            .unwrap_or_else(|err| UpdateChannelSettingsResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn schedule_message(
+        &self,
+        request: Request<ScheduleMessageRequest>,
+    ) -> Result<Response<ScheduleMessageResponse>, Status> {
+        let resp =
+            self._schedule_message(request)
+                .await
+                .unwrap_or_else(|err| ScheduleMessageResponse {
+                    result: Some(schedule_message_response::Result::Error(err.into())),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_scheduled_messages(
+        &self,
+        request: Request<ListScheduledMessagesRequest>,
+    ) -> Result<Response<ListScheduledMessagesResponse>, Status> {
+        let resp = self
+            ._list_scheduled_messages(request)
+            .await
+            .unwrap_or_else(|err| ListScheduledMessagesResponse {
+                scheduled: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn update_scheduled_message(
+        &self,
+        request: Request<UpdateScheduledMessageRequest>,
+    ) -> Result<Response<UpdateScheduledMessageResponse>, Status> {
+        let resp = self
+            ._update_scheduled_message(request)
+            .await
+            .unwrap_or_else(|err| UpdateScheduledMessageResponse {
+                result: Some(update_scheduled_message_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn cancel_scheduled_message(
+        &self,
+        request: Request<CancelScheduledMessageRequest>,
+    ) -> Result<Response<CancelScheduledMessageResponse>, Status> {
+        let resp = self
+            ._cancel_scheduled_message(request)
+            .await
+            .unwrap_or_else(|err| CancelScheduledMessageResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn create_webhook(
+        &self,
+        request: Request<CreateWebhookRequest>,
+    ) -> Result<Response<CreateWebhookResponse>, Status> {
+        let resp =
+            self._create_webhook(request)
+                .await
+                .unwrap_or_else(|err| CreateWebhookResponse {
+                    result: Some(create_webhook_response::Result::Error(err.into())),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_webhooks(
+        &self,
+        request: Request<ListWebhooksRequest>,
+    ) -> Result<Response<ListWebhooksResponse>, Status> {
+        let resp = self
+            ._list_webhooks(request)
+            .await
+            .unwrap_or_else(|err| ListWebhooksResponse {
+                webhooks: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn delete_webhook(
+        &self,
+        request: Request<DeleteWebhookRequest>,
+    ) -> Result<Response<DeleteWebhookResponse>, Status> {
+        let resp =
+            self._delete_webhook(request)
+                .await
+                .unwrap_or_else(|err| DeleteWebhookResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        request: Request<ListWebhookDeliveriesRequest>,
+    ) -> Result<Response<ListWebhookDeliveriesResponse>, Status> {
+        let resp = self
+            ._list_webhook_deliveries(request)
+            .await
+            .unwrap_or_else(|err| ListWebhookDeliveriesResponse {
+                deliveries: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn create_incoming_webhook(
+        &self,
+        request: Request<CreateIncomingWebhookRequest>,
+    ) -> Result<Response<CreateIncomingWebhookResponse>, Status> {
+        let resp = self
+            ._create_incoming_webhook(request)
+            .await
+            .unwrap_or_else(|err| CreateIncomingWebhookResponse {
+                result: Some(create_incoming_webhook_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_incoming_webhooks(
+        &self,
+        request: Request<ListIncomingWebhooksRequest>,
+    ) -> Result<Response<ListIncomingWebhooksResponse>, Status> {
+        let resp = self
+            ._list_incoming_webhooks(request)
+            .await
+            .unwrap_or_else(|err| ListIncomingWebhooksResponse {
+                webhooks: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn delete_incoming_webhook(
+        &self,
+        request: Request<DeleteIncomingWebhookRequest>,
+    ) -> Result<Response<DeleteIncomingWebhookResponse>, Status> {
+        let resp = self
+            ._delete_incoming_webhook(request)
+            .await
+            .unwrap_or_else(|err| DeleteIncomingWebhookResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn post_webhook_message(
+        &self,
+        request: Request<PostWebhookMessageRequest>,
+    ) -> Result<Response<PostWebhookMessageResponse>, Status> {
+        Ok(error::respond(
+            self._post_webhook_message(request).await,
+            |err| PostWebhookMessageResponse {
+                result: Some(post_webhook_message_response::Result::Error(err.into())),
+            },
+        ))
+    }
+
+    async fn create_invite(
+        &self,
+        request: Request<CreateInviteRequest>,
+    ) -> Result<Response<CreateInviteResponse>, Status> {
+        let resp = self
+            ._create_invite(request)
+            .await
+            .unwrap_or_else(|err| CreateInviteResponse {
+                result: Some(create_invite_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_invites(
+        &self,
+        request: Request<ListInvitesRequest>,
+    ) -> Result<Response<ListInvitesResponse>, Status> {
+        let resp = self
+            ._list_invites(request)
+            .await
+            .unwrap_or_else(|err| ListInvitesResponse {
+                invites: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn revoke_invite(
+        &self,
+        request: Request<RevokeInviteRequest>,
+    ) -> Result<Response<RevokeInviteResponse>, Status> {
+        let resp = self
+            ._revoke_invite(request)
+            .await
+            .unwrap_or_else(|err| RevokeInviteResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn join_channel(
+        &self,
+        request: Request<JoinChannelRequest>,
+    ) -> Result<Response<JoinChannelResponse>, Status> {
+        let resp = self
+            ._join_channel(request)
+            .await
+            .unwrap_or_else(|err| JoinChannelResponse {
+                result: Some(join_channel_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    type ExportChannelStream = BoxStream<ExportChannelResponse>;
+
+    async fn export_channel(
+        &self,
+        request: Request<ExportChannelRequest>,
+    ) -> Result<Response<Self::ExportChannelStream>, Status> {
+        let resp = self._export_channel(request).await.unwrap_or_else(|err| {
+            Box::pin(VecStream::once(Ok(ExportChannelResponse {
+                result: Some(export_channel_response::Result::Error(err.into())),
+            })))
+        });
+
+        Ok(Response::new(resp))
+    }
+
+    type ConnectBotStream = BoxStream<ConnectBotResponse>;
+
+    async fn connect_bot(
+        &self,
+        request: Request<ConnectBotRequest>,
+    ) -> Result<Response<Self::ConnectBotStream>, Status> {
+        let resp = self._connect_bot(request).await.unwrap_or_else(|err| {
+            Box::pin(VecStream::once(Ok(ConnectBotResponse {
+                result: Some(connect_bot_response::Result::Error(err.into())),
+            })))
+        });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn reply_command(
+        &self,
+        request: Request<ReplyCommandRequest>,
+    ) -> Result<Response<ReplyCommandResponse>, Status> {
+        Ok(error::respond(self._reply_command(request).await, |err| {
+            ReplyCommandResponse {
+                result: Some(reply_command_response::Result::Error(err.into())),
+            }
+        }))
+    }
+}
+
+fn to_proto_event(event: ChannelEvent) -> elysium_rust::chat::v1::ChannelEvent {
+    let event = match event {
+        ChannelEvent::MessageCreated(message) => {
+            channel_event::Event::MessageCreated(message.into())
+        }
+        ChannelEvent::MessageUpdated(message) => {
+            channel_event::Event::MessageUpdated(message.into())
+        }
+        ChannelEvent::MessageDeleted {
+            channel_id,
+            message_id,
+        } => channel_event::Event::MessageDeleted(MessageDeleted {
+            channel_id,
+            message_id,
+        }),
+        ChannelEvent::MessagePinned(pin) => channel_event::Event::MessagePinned(MessagePinned {
+            channel_id: pin.channel_id,
+            message_id: pin.message_id,
+            user_id: pin.user_id,
+            pinned_at: Some(pin.pinned_at.into()),
+        }),
+        ChannelEvent::MessageUnpinned {
+            channel_id,
+            message_id,
+        } => channel_event::Event::MessageUnpinned(MessageUnpinned {
+            channel_id,
+            message_id,
+        }),
+        ChannelEvent::MemberChanged {
+            channel_id,
+            user_id,
+            permission,
+        } => channel_event::Event::MemberChanged(MemberChanged {
+            channel_id,
+            user_id,
+            permission: permission as i32,
+        }),
+        ChannelEvent::Ephemeral { message, .. } => {
+            channel_event::Event::EphemeralMessage(message.into())
+        }
+    };
+
+    elysium_rust::chat::v1::ChannelEvent { event: Some(event) }
+}
+
+fn to_proto_invite(invite: chat::Invite) -> Invite {
+    Invite {
+        code: invite.code,
+        channel_id: invite.channel_id,
+        permission: invite.permission,
+        max_uses: invite.max_uses,
+        uses: invite.uses,
+        expires_at: Some(invite.expires_at.into()),
+        created_by: invite.created_by,
+        created_at: Some(invite.created_at.into()),
+    }
+}
+
+fn to_proto_scheduled(scheduled: chat::ScheduledMessage) -> ScheduledMessage {
+    ScheduledMessage {
+        schedule_id: scheduled.schedule_id,
+        channel_id: scheduled.channel_id,
+        content: Some(scheduled.content.into()),
+        send_at: Some(scheduled.send_at.into()),
+        ttl: scheduled.ttl,
+    }
+}
+
+fn to_proto_webhook(webhook: webhook::Webhook, with_secret: bool) -> Webhook {
+    Webhook {
+        webhook_id: webhook.webhook_id,
+        channel_id: webhook.channel_id,
+        url: webhook.url,
+        secret: if with_secret {
+            webhook.secret
+        } else {
+            String::new()
+        },
+        events: webhook.events,
+        created_at: Some(webhook.created_at.into()),
+    }
+}
+
+fn to_proto_incoming(incoming: webhook::IncomingWebhook) -> IncomingWebhook {
+    IncomingWebhook {
+        webhook_id: incoming.webhook_id,
+        channel_id: incoming.channel_id,
+        name: incoming.name,
+        token: String::new(),
+        created_at: Some(incoming.created_at.into()),
     }
 }
@@ -77,7 +77,23 @@ async fn clear_state(state: &ServerState) {
 REMOVE TABLE user;
 REMOVE TABLE channel;
 REMOVE TABLE message;
-REMOVE TABLE resource;"#,
+REMOVE TABLE resource;
+REMOVE TABLE read_state;
+REMOVE TABLE pin;
+REMOVE TABLE channel_settings;
+REMOVE TABLE scheduled_message;
+REMOVE TABLE webhook;
+REMOVE TABLE webhook_delivery;
+REMOVE TABLE incoming_webhook;
+REMOVE TABLE bot;
+REMOVE TABLE report;
+REMOVE TABLE sanction;
+REMOVE TABLE filter_rule;
+REMOVE TABLE invite;
+REMOVE TABLE upload_session;
+REMOVE TABLE blob;
+REMOVE TABLE storage_quota;
+REMOVE TABLE storage_usage;"#,
         )
         .await
         .expect("Failed to drop user table");
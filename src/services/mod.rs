@@ -1,9 +1,11 @@
 pub mod chat;
 pub mod general;
+pub mod moderation;
 pub mod resource;
 pub mod user;
 
 pub type GeneralService = general::Service;
+pub type ModerationService = moderation::Service;
 pub type ChatService = chat::Service;
 pub type ResourceService = resource::Service;
 pub type UserService = user::Service;
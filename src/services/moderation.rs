@@ -0,0 +1,729 @@
+use crate::error::Error;
+use crate::events::ChannelEvent;
+use crate::filter::FilterRule;
+use crate::moderation::{Report, Sanction};
+use crate::state::ServerState;
+use crate::{auth, chat, filter, moderation, user, utils};
+use elysium_rust::Timestamp;
+use elysium_rust::chat::v1::ChannelPermission;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::moderation::v1::moderation_service_server::ModerationService;
+use elysium_rust::moderation::v1::{
+    BanUserRequest, BanUserResponse, CreateFilterRuleRequest, CreateFilterRuleResponse,
+    DeleteFilterRuleRequest, DeleteFilterRuleResponse, FilterAction, FilterKind,
+    ListFilterRulesRequest, ListFilterRulesResponse, ListReportsRequest, ListReportsResponse,
+    ListSanctionsRequest, ListSanctionsResponse, MuteUserRequest, MuteUserResponse,
+    ReportMessageRequest, ReportMessageResponse, ReportStatus, ResolveReportRequest,
+    ResolveReportResponse, RevokeSanctionRequest, RevokeSanctionResponse, TestFilterRuleRequest,
+    TestFilterRuleResponse, TimeoutUserRequest, TimeoutUserResponse, ban_user_response,
+    create_filter_rule_response, mute_user_response, timeout_user_response,
+};
+use elysium_rust::user::v1::UserRole;
+use elysium_rust::{User, moderation::v1 as proto};
+use tonic::{Request, Response, Status};
+
+pub struct Service {
+    state: ServerState,
+}
+
+impl Service {
+    pub fn new(state: ServerState) -> Self {
+        Self { state }
+    }
+
+    async fn _report_message(
+        &self,
+        request: Request<ReportMessageRequest>,
+    ) -> Result<ReportMessageResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let message = chat::get_msg(database, &args.message_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Message not found"))?;
+
+        // Only members can see, and thus report, a message
+        chat::get_channel_member_perm(database, &message.channel_id, &user.user_id).await?;
+
+        moderation::create_report(
+            database,
+            Report {
+                report_id: moderation::build_id(),
+                message_id: message.message_id,
+                channel_id: message.channel_id,
+                reporter_id: user.user_id,
+                user_id: message.user_id,
+                content: message.content,
+                reason: args.reason,
+                status: moderation::REPORT_OPEN.to_string(),
+                reviewer_id: String::new(),
+                resolution: String::new(),
+                created_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        Ok(ReportMessageResponse { error: None })
+    }
+
+    async fn _list_reports(
+        &self,
+        request: Request<ListReportsRequest>,
+    ) -> Result<ListReportsResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Supervisor).await?;
+
+        let args = request.into_inner();
+        let status = ReportStatus::try_from(args.status).map_err(|_| Error::invalid_argument())?;
+
+        let reports =
+            moderation::get_reports(database, from_proto_status(status), args.limit).await?;
+
+        Ok(ListReportsResponse {
+            reports: reports.into_iter().map(to_proto_report).collect(),
+            error: None,
+        })
+    }
+
+    async fn _resolve_report(
+        &self,
+        request: Request<ResolveReportRequest>,
+    ) -> Result<ResolveReportResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify_role(database, &request, UserRole::Supervisor).await?;
+        let args = request.into_inner();
+
+        let mut report = moderation::get_report(database, &args.report_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Report not found"))?;
+
+        if report.status != moderation::REPORT_OPEN {
+            return Err(Error::new(
+                ErrorCode::AlreadyExists,
+                "Report was already reviewed",
+            ));
+        }
+
+        if args.delete_message && chat::msg_exists(database, &report.message_id).await? {
+            if let Some(pin) = chat::delete_message(database, &report.message_id).await? {
+                self.state.events().publish(ChannelEvent::MessageUnpinned {
+                    channel_id: pin.channel_id,
+                    message_id: pin.message_id,
+                });
+            }
+
+            self.state.events().publish(ChannelEvent::MessageDeleted {
+                channel_id: report.channel_id.clone(),
+                message_id: report.message_id.clone(),
+            });
+        }
+
+        report.status = if args.dismiss {
+            moderation::REPORT_DISMISSED
+        } else {
+            moderation::REPORT_RESOLVED
+        }
+        .to_string();
+        report.reviewer_id = user.user_id;
+        report.resolution = args.resolution;
+
+        moderation::update_report(database, report).await?;
+
+        Ok(ResolveReportResponse { error: None })
+    }
+
+    async fn _mute_user(
+        &self,
+        request: Request<MuteUserRequest>,
+    ) -> Result<MuteUserResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify_role(database, &request, UserRole::Supervisor).await?;
+        let args = request.into_inner();
+
+        if !chat::channel_exists(database, &args.channel_id).await? {
+            return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+        }
+
+        let sanction = self
+            .sanction(
+                &user,
+                moderation::SANCTION_MUTE,
+                args.user_id,
+                args.channel_id,
+                args.reason,
+                args.duration,
+            )
+            .await?;
+
+        Ok(MuteUserResponse {
+            result: Some(mute_user_response::Result::Sanction(to_proto_sanction(
+                sanction,
+            ))),
+        })
+    }
+
+    async fn _timeout_user(
+        &self,
+        request: Request<TimeoutUserRequest>,
+    ) -> Result<TimeoutUserResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify_role(database, &request, UserRole::Supervisor).await?;
+        let args = request.into_inner();
+
+        if args.duration == 0 {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                "Timeouts require a duration",
+            ));
+        }
+
+        if !chat::channel_exists(database, &args.channel_id).await? {
+            return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+        }
+
+        let sanction = self
+            .sanction(
+                &user,
+                moderation::SANCTION_TIMEOUT,
+                args.user_id,
+                args.channel_id,
+                args.reason,
+                args.duration,
+            )
+            .await?;
+
+        Ok(TimeoutUserResponse {
+            result: Some(timeout_user_response::Result::Sanction(to_proto_sanction(
+                sanction,
+            ))),
+        })
+    }
+
+    async fn _ban_user(&self, request: Request<BanUserRequest>) -> Result<BanUserResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify_role(database, &request, UserRole::Supervisor).await?;
+        let args = request.into_inner();
+
+        let sanction = self
+            .sanction(
+                &user,
+                moderation::SANCTION_BAN,
+                args.user_id,
+                String::new(),
+                args.reason,
+                args.duration,
+            )
+            .await?;
+
+        Ok(BanUserResponse {
+            result: Some(ban_user_response::Result::Sanction(to_proto_sanction(
+                sanction,
+            ))),
+        })
+    }
+
+    async fn _revoke_sanction(
+        &self,
+        request: Request<RevokeSanctionRequest>,
+    ) -> Result<RevokeSanctionResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Supervisor).await?;
+
+        moderation::revoke_sanction(database, &request.into_inner().sanction_id).await?;
+
+        Ok(RevokeSanctionResponse { error: None })
+    }
+
+    async fn _list_sanctions(
+        &self,
+        request: Request<ListSanctionsRequest>,
+    ) -> Result<ListSanctionsResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Supervisor).await?;
+
+        let sanctions =
+            moderation::get_user_sanctions(database, &request.into_inner().user_id).await?;
+
+        Ok(ListSanctionsResponse {
+            sanctions: sanctions.into_iter().map(to_proto_sanction).collect(),
+            error: None,
+        })
+    }
+
+    async fn _create_filter_rule(
+        &self,
+        request: Request<CreateFilterRuleRequest>,
+    ) -> Result<CreateFilterRuleResponse, Error> {
+        let database = self.state.database();
+
+        let user = self
+            .verify_filter_access(&request, &request.get_ref().channel_id)
+            .await?;
+        let args = request.into_inner();
+
+        let kind = from_proto_kind(
+            FilterKind::try_from(args.kind).map_err(|_| Error::invalid_argument())?,
+        );
+        let action = from_proto_action(
+            FilterAction::try_from(args.action).map_err(|_| Error::invalid_argument())?,
+        );
+
+        if !args.channel_id.is_empty() && !chat::channel_exists(database, &args.channel_id).await? {
+            return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+        }
+
+        // Only store rules which compile
+        filter::compile(kind, &args.pattern)?;
+
+        let rule = filter::create_rule(
+            database,
+            FilterRule {
+                rule_id: filter::build_rule_id(),
+                channel_id: args.channel_id,
+                kind: kind.to_string(),
+                pattern: args.pattern,
+                action: action.to_string(),
+                created_by: user.user_id,
+                created_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        self.state.filters().reload(database).await?;
+
+        Ok(CreateFilterRuleResponse {
+            result: Some(create_filter_rule_response::Result::Rule(to_proto_rule(
+                rule,
+            ))),
+        })
+    }
+
+    async fn _list_filter_rules(
+        &self,
+        request: Request<ListFilterRulesRequest>,
+    ) -> Result<ListFilterRulesResponse, Error> {
+        let database = self.state.database();
+
+        self.verify_filter_access(&request, &request.get_ref().channel_id)
+            .await?;
+
+        let rules = filter::get_channel_rules(database, &request.into_inner().channel_id).await?;
+
+        Ok(ListFilterRulesResponse {
+            rules: rules.into_iter().map(to_proto_rule).collect(),
+            error: None,
+        })
+    }
+
+    async fn _delete_filter_rule(
+        &self,
+        request: Request<DeleteFilterRuleRequest>,
+    ) -> Result<DeleteFilterRuleResponse, Error> {
+        let database = self.state.database();
+
+        let rule = filter::get_rule(database, &request.get_ref().rule_id).await?;
+
+        // Unknown rules are checked like server-wide ones, so only supervisors learn about them
+        let channel_id = rule.as_ref().map_or("", |rule| rule.channel_id.as_str());
+        self.verify_filter_access(&request, channel_id).await?;
+
+        let rule = rule.ok_or(Error::new(ErrorCode::NotFound, "Filter rule not found"))?;
+
+        filter::delete_rule(database, &rule.rule_id).await?;
+
+        self.state.filters().reload(database).await?;
+
+        Ok(DeleteFilterRuleResponse { error: None })
+    }
+
+    async fn _test_filter_rule(
+        &self,
+        request: Request<TestFilterRuleRequest>,
+    ) -> Result<TestFilterRuleResponse, Error> {
+        self.verify_filter_access(&request, &request.get_ref().channel_id)
+            .await?;
+
+        let args = request.into_inner();
+
+        let kind = from_proto_kind(
+            FilterKind::try_from(args.kind).map_err(|_| Error::invalid_argument())?,
+        );
+        let action = from_proto_action(
+            FilterAction::try_from(args.action).map_err(|_| Error::invalid_argument())?,
+        );
+
+        let regex = filter::compile(kind, &args.pattern)?;
+
+        let matches = regex
+            .find_iter(&args.text)
+            .map(|m| m.as_str().to_string())
+            .collect::<Vec<_>>();
+
+        let text = if action == filter::ACTION_MASK {
+            filter::mask(&regex, &args.text)
+        } else {
+            args.text
+        };
+
+        Ok(TestFilterRuleResponse {
+            rejected: action == filter::ACTION_REJECT && !matches.is_empty(),
+            flagged: action == filter::ACTION_FLAG && !matches.is_empty(),
+            matches,
+            text,
+            error: None,
+        })
+    }
+
+    /// Server-wide rules are managed by supervisors, channel rules also by channel managers.
+    async fn verify_filter_access<T>(
+        &self,
+        request: &Request<T>,
+        channel_id: &str,
+    ) -> Result<User, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, request).await?;
+
+        if user.role >= UserRole::Supervisor as i32 {
+            return Ok(user);
+        }
+
+        if !channel_id.is_empty()
+            && chat::get_channel_member_perm(database, channel_id, &user.user_id).await?
+                == ChannelPermission::Manager
+        {
+            return Ok(user);
+        }
+
+        Err(Error::new(
+            ErrorCode::Unauthorized,
+            "User has no permission to manage filter rules",
+        ))
+    }
+
+    /// Issues a sanction, `duration` is in seconds with `0` meaning permanent.
+    async fn sanction(
+        &self,
+        issuer: &User,
+        kind: &str,
+        user_id: String,
+        channel_id: String,
+        reason: String,
+        duration: u64,
+    ) -> Result<Sanction, Error> {
+        let database = self.state.database();
+
+        let target = user::get(database, &user_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?;
+
+        if target.role >= issuer.role {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "Cannot sanction users with the same or a higher role",
+            ));
+        }
+
+        let now = utils::get_timestamp();
+        let expires_at = if duration == 0 {
+            Timestamp { millis: 0 }
+        } else {
+            Timestamp {
+                millis: now.millis + duration * 1000,
+            }
+        };
+
+        moderation::create_sanction(
+            database,
+            Sanction {
+                sanction_id: moderation::build_id(),
+                kind: kind.to_string(),
+                user_id,
+                channel_id,
+                reason,
+                issuer_id: issuer.user_id.clone(),
+                created_at: now,
+                expires_at,
+                revoked: false,
+            },
+        )
+        .await
+    }
+}
+
+#[tonic::async_trait]
+impl ModerationService for Service {
+    async fn create_filter_rule(
+        &self,
+        request: Request<CreateFilterRuleRequest>,
+    ) -> Result<Response<CreateFilterRuleResponse>, Status> {
+        let resp = self
+            ._create_filter_rule(request)
+            .await
+            .unwrap_or_else(|err| CreateFilterRuleResponse {
+                result: Some(create_filter_rule_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_filter_rules(
+        &self,
+        request: Request<ListFilterRulesRequest>,
+    ) -> Result<Response<ListFilterRulesResponse>, Status> {
+        let resp = self
+            ._list_filter_rules(request)
+            .await
+            .unwrap_or_else(|err| ListFilterRulesResponse {
+                rules: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn delete_filter_rule(
+        &self,
+        request: Request<DeleteFilterRuleRequest>,
+    ) -> Result<Response<DeleteFilterRuleResponse>, Status> {
+        let resp = self
+            ._delete_filter_rule(request)
+            .await
+            .unwrap_or_else(|err| DeleteFilterRuleResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn test_filter_rule(
+        &self,
+        request: Request<TestFilterRuleRequest>,
+    ) -> Result<Response<TestFilterRuleResponse>, Status> {
+        let resp =
+            self._test_filter_rule(request)
+                .await
+                .unwrap_or_else(|err| TestFilterRuleResponse {
+                    matches: Vec::new(),
+                    text: String::new(),
+                    rejected: false,
+                    flagged: false,
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn report_message(
+        &self,
+        request: Request<ReportMessageRequest>,
+    ) -> Result<Response<ReportMessageResponse>, Status> {
+        let resp =
+            self._report_message(request)
+                .await
+                .unwrap_or_else(|err| ReportMessageResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_reports(
+        &self,
+        request: Request<ListReportsRequest>,
+    ) -> Result<Response<ListReportsResponse>, Status> {
+        let resp = self
+            ._list_reports(request)
+            .await
+            .unwrap_or_else(|err| ListReportsResponse {
+                reports: Vec::new(),
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn resolve_report(
+        &self,
+        request: Request<ResolveReportRequest>,
+    ) -> Result<Response<ResolveReportResponse>, Status> {
+        let resp =
+            self._resolve_report(request)
+                .await
+                .unwrap_or_else(|err| ResolveReportResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn mute_user(
+        &self,
+        request: Request<MuteUserRequest>,
+    ) -> Result<Response<MuteUserResponse>, Status> {
+        let resp = self
+            ._mute_user(request)
+            .await
+            .unwrap_or_else(|err| MuteUserResponse {
+                result: Some(mute_user_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn timeout_user(
+        &self,
+        request: Request<TimeoutUserRequest>,
+    ) -> Result<Response<TimeoutUserResponse>, Status> {
+        let resp = self
+            ._timeout_user(request)
+            .await
+            .unwrap_or_else(|err| TimeoutUserResponse {
+                result: Some(timeout_user_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn ban_user(
+        &self,
+        request: Request<BanUserRequest>,
+    ) -> Result<Response<BanUserResponse>, Status> {
+        let resp = self
+            ._ban_user(request)
+            .await
+            .unwrap_or_else(|err| BanUserResponse {
+                result: Some(ban_user_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn revoke_sanction(
+        &self,
+        request: Request<RevokeSanctionRequest>,
+    ) -> Result<Response<RevokeSanctionResponse>, Status> {
+        let resp =
+            self._revoke_sanction(request)
+                .await
+                .unwrap_or_else(|err| RevokeSanctionResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_sanctions(
+        &self,
+        request: Request<ListSanctionsRequest>,
+    ) -> Result<Response<ListSanctionsResponse>, Status> {
+        let resp =
+            self._list_sanctions(request)
+                .await
+                .unwrap_or_else(|err| ListSanctionsResponse {
+                    sanctions: Vec::new(),
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+}
+
+fn from_proto_status(status: ReportStatus) -> &'static str {
+    match status {
+        ReportStatus::Open => moderation::REPORT_OPEN,
+        ReportStatus::Resolved => moderation::REPORT_RESOLVED,
+        ReportStatus::Dismissed => moderation::REPORT_DISMISSED,
+    }
+}
+
+fn to_proto_status(status: &str) -> ReportStatus {
+    match status {
+        moderation::REPORT_RESOLVED => ReportStatus::Resolved,
+        moderation::REPORT_DISMISSED => ReportStatus::Dismissed,
+        _ => ReportStatus::Open,
+    }
+}
+
+fn to_proto_report(report: Report) -> proto::Report {
+    proto::Report {
+        report_id: report.report_id,
+        message_id: report.message_id,
+        channel_id: report.channel_id,
+        reporter_id: report.reporter_id,
+        user_id: report.user_id,
+        content: Some(report.content.into()),
+        reason: report.reason,
+        status: to_proto_status(&report.status) as i32,
+        reviewer_id: report.reviewer_id,
+        resolution: report.resolution,
+        created_at: Some(report.created_at.into()),
+    }
+}
+
+fn to_proto_sanction(sanction: Sanction) -> proto::Sanction {
+    proto::Sanction {
+        sanction_id: sanction.sanction_id,
+        kind: sanction.kind,
+        user_id: sanction.user_id,
+        channel_id: sanction.channel_id,
+        reason: sanction.reason,
+        issuer_id: sanction.issuer_id,
+        created_at: Some(sanction.created_at.into()),
+        expires_at: Some(sanction.expires_at.into()),
+        revoked: sanction.revoked,
+    }
+}
+
+fn from_proto_kind(kind: FilterKind) -> &'static str {
+    match kind {
+        FilterKind::Word => filter::KIND_WORD,
+        FilterKind::Regex => filter::KIND_REGEX,
+        FilterKind::Domain => filter::KIND_DOMAIN,
+    }
+}
+
+fn to_proto_kind(kind: &str) -> FilterKind {
+    match kind {
+        filter::KIND_REGEX => FilterKind::Regex,
+        filter::KIND_DOMAIN => FilterKind::Domain,
+        _ => FilterKind::Word,
+    }
+}
+
+fn from_proto_action(action: FilterAction) -> &'static str {
+    match action {
+        FilterAction::Reject => filter::ACTION_REJECT,
+        FilterAction::Mask => filter::ACTION_MASK,
+        FilterAction::Flag => filter::ACTION_FLAG,
+    }
+}
+
+fn to_proto_action(action: &str) -> FilterAction {
+    match action {
+        filter::ACTION_MASK => FilterAction::Mask,
+        filter::ACTION_FLAG => FilterAction::Flag,
+        _ => FilterAction::Reject,
+    }
+}
+
+fn to_proto_rule(rule: FilterRule) -> proto::FilterRule {
+    proto::FilterRule {
+        rule_id: rule.rule_id,
+        channel_id: rule.channel_id,
+        kind: to_proto_kind(&rule.kind) as i32,
+        pattern: rule.pattern,
+        action: to_proto_action(&rule.action) as i32,
+        created_by: rule.created_by,
+        created_at: Some(rule.created_at.into()),
+    }
+}
@@ -1,20 +1,31 @@
 use crate::error::Error;
 use crate::resource::ResourceDescriptor;
 use crate::state::ServerState;
+use crate::upload::UploadSession;
 use crate::utils::{SafeStreaming, VecStream};
-use crate::{auth, resource, utils};
+use crate::{auth, chat, config, gc, media, quota, resource, upload, user, utils};
 use elysium_rust::common::v1::ErrorCode;
 use elysium_rust::resource::v1::resource_service_server::ResourceService;
 use elysium_rust::resource::v1::upload_request::Payload;
 use elysium_rust::resource::v1::{
-    DownloadRequest, DownloadResponse, GetResourceMetaRequest, GetResourceMetaResponse,
-    UploadRequest, UploadResponse, download_response, get_resource_meta_response,
+    CollectGarbageRequest, CollectGarbageResponse, CommitUploadRequest, CommitUploadResponse,
+    DeleteResourceRequest, DeleteResourceResponse, DownloadRequest, DownloadResponse,
+    GetResourceMetaRequest, GetResourceMetaResponse, GetStorageUsageRequest,
+    GetStorageUsageResponse, GetUploadStatusRequest, GetUploadStatusResponse, InitUploadRequest,
+    InitUploadResponse, ListResourcesRequest, ListResourcesResponse, Resource,
+    SetStorageQuotaRequest, SetStorageQuotaResponse, StorageUsage, UploadChunkRequest,
+    UploadChunkResponse, UploadRequest, UploadResponse, commit_upload_response, download_response,
+    get_resource_meta_response, init_upload_response,
 };
+use elysium_rust::user::v1::UserRole;
 use elysium_rust::{ResourceId, ResourceMeta};
 use tonic::codegen::BoxStream;
 use tonic::codegen::tokio_stream::StreamExt;
 use tonic::{Request, Response, Status, Streaming};
 
+/// Maximum number of resources returned by one listing, also used when no limit is given.
+const MAX_LIST_LIMIT: u32 = 100;
+
 pub struct Service {
     state: ServerState,
 }
@@ -40,32 +51,26 @@ impl Service {
         let resource_id =
             ResourceId::try_from(meta_req.resource_id.ok_or(Error::invalid_argument())?)?;
 
-        let mut meta =
+        let meta =
             ResourceMeta::try_from(match meta_req.payload.ok_or(Error::invalid_argument())? {
                 Payload::Meta(meta) => Ok(meta),
                 Payload::Data(_) => Err(Error::invalid_argument()),
             }?)?;
 
-        meta.timestamp = utils::get_timestamp();
+        resource::validate_id(&resource_id)?;
 
-        let desc = ResourceDescriptor {
-            resource_id,
-            meta,
-            user_id: user.user_id.clone(),
-        };
+        if meta.size < 0 {
+            return Err(Error::invalid_argument());
+        }
 
-        if !resource::is_upload_authorized(database, &desc, &user.user_id).await? {
+        if !resource::is_upload_authorized(database, &resource_id, &user.user_id).await? {
             return Err(Error::new(
                 ErrorCode::Unauthorized,
                 "User does not have write permissions",
             ));
         }
 
-        let desc = if let Some(desc) = resource::get(database, &desc.resource_id).await? {
-            desc
-        } else {
-            resource::create(database, desc).await?
-        };
+        quota::check(database, &user, &resource_id, meta.size as u64).await?;
 
         let stream = stream.into_inner().map(|req| match req {
             Ok(req) => match req.payload.ok_or(Error::invalid_argument())? {
@@ -79,11 +84,163 @@ impl Service {
             )),
         });
 
-        resource::write(desc.resource_id, stream).await?;
+        let written = resource::write(database, Some(meta.size as u64), stream).await?;
+
+        media::save_upload(database, resource_id, meta, user.user_id, written).await?;
 
         Ok(UploadResponse { error: None })
     }
 
+    async fn _init_upload(
+        &self,
+        request: Request<InitUploadRequest>,
+    ) -> Result<InitUploadResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        let resource_id = ResourceId::try_from(args.resource_id.ok_or(Error::invalid_argument())?)?;
+        let meta = ResourceMeta::try_from(args.meta.ok_or(Error::invalid_argument())?)?;
+
+        resource::validate_id(&resource_id)?;
+
+        let max_size = config::get().service_max_resource_size;
+
+        if meta.size < 0 || meta.size as u64 > max_size {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                format!("Resource size must be between 0 and {max_size} bytes"),
+            ));
+        }
+
+        if !resource::is_upload_authorized(database, &resource_id, &user.user_id).await? {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User does not have write permissions",
+            ));
+        }
+
+        quota::check(database, &user, &resource_id, meta.size as u64).await?;
+
+        let now = utils::get_timestamp();
+
+        let session = upload::create_session(
+            database,
+            UploadSession {
+                upload_id: upload::build_upload_id(),
+                resource_id,
+                meta,
+                user_id: user.user_id,
+                offset: 0,
+                created_at: now.clone(),
+                updated_at: now,
+            },
+        )
+        .await?;
+
+        Ok(InitUploadResponse {
+            result: Some(init_upload_response::Result::UploadId(session.upload_id)),
+        })
+    }
+
+    async fn _upload_chunk(
+        &self,
+        request: Request<Streaming<UploadChunkRequest>>,
+    ) -> Result<UploadChunkResponse, Error> {
+        let database = self.state.database();
+        let user = auth::verify(database, &request).await?;
+        let mut stream = SafeStreaming::new(request.into_inner());
+
+        let mut session: Option<UploadSession> = None;
+
+        while let Some(chunk) = stream.next_safe().await {
+            let chunk = chunk?;
+
+            // All chunks of one call have to belong to the same upload
+            let current = match session.take() {
+                Some(current) if current.upload_id == chunk.upload_id => current,
+                Some(_) => return Err(Error::invalid_argument()),
+                None => self.get_session(&chunk.upload_id, &user.user_id).await?,
+            };
+            let current = session.insert(current);
+
+            upload::write_chunk(
+                database,
+                current,
+                chunk.offset,
+                &chunk.data,
+                &chunk.checksum,
+            )
+            .await?;
+        }
+
+        Ok(UploadChunkResponse {
+            offset: session.map(|session| session.offset).unwrap_or(0),
+            error: None,
+        })
+    }
+
+    async fn _get_upload_status(
+        &self,
+        request: Request<GetUploadStatusRequest>,
+    ) -> Result<GetUploadStatusResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let session = self
+            .get_session(&request.into_inner().upload_id, &user.user_id)
+            .await?;
+
+        Ok(GetUploadStatusResponse {
+            offset: session.offset,
+            error: None,
+        })
+    }
+
+    async fn _commit_upload(
+        &self,
+        request: Request<CommitUploadRequest>,
+    ) -> Result<CommitUploadResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let session = self
+            .get_session(&request.into_inner().upload_id, &user.user_id)
+            .await?;
+
+        // Other uploads may have used up the quota since this one was started
+        quota::check(
+            database,
+            &user,
+            &session.resource_id,
+            session.meta.size as u64,
+        )
+        .await?;
+
+        let desc = upload::commit(database, session).await?;
+
+        Ok(CommitUploadResponse {
+            result: Some(commit_upload_response::Result::Meta(desc.meta.into())),
+        })
+    }
+
+    /// Gets an upload session, which only its creator may continue.
+    async fn get_session(&self, upload_id: &str, user_id: &str) -> Result<UploadSession, Error> {
+        let session = upload::get_session(self.state.database(), upload_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Upload not found"))?;
+
+        if session.user_id != user_id {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "Upload was started by another user",
+            ));
+        }
+
+        Ok(session)
+    }
+
     async fn _download(
         &self,
         request: Request<DownloadRequest>,
@@ -91,12 +248,8 @@ impl Service {
         let database = self.state.database();
 
         let user = auth::verify(database, &request).await?;
-        let resource_id = ResourceId::try_from(
-            request
-                .into_inner()
-                .resource_id
-                .ok_or(Error::invalid_argument())?,
-        )?;
+        let args = request.into_inner();
+        let resource_id = ResourceId::try_from(args.resource_id.ok_or(Error::invalid_argument())?)?;
         let desc = resource::get(database, &resource_id)
             .await?
             .ok_or(Error::new(ErrorCode::NotFound, "Resource not found"))?;
@@ -105,11 +258,62 @@ impl Service {
             return Err(Error::new(ErrorCode::Unauthorized, "User not authorized"));
         }
 
+        // Thumbnails are authorized through the resource they were derived from
+        let desc = if args.thumbnail_size == 0 {
+            desc
+        } else if media::THUMBNAIL_SIZES.contains(&args.thumbnail_size) {
+            resource::get(
+                database,
+                &media::build_thumbnail_id(&resource_id, args.thumbnail_size),
+            )
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Thumbnail not found"))?
+        } else {
+            return Err(Error::new(
+                ErrorCode::InvalidFormat,
+                format!("Thumbnail size must be one of {:?}", media::THUMBNAIL_SIZES),
+            ));
+        };
+
+        let etag = resource::etag(&desc);
+        let mut meta = desc.meta.clone();
+
+        meta.metadata
+            .insert(resource::ETAG_METADATA_KEY.to_string(), etag.clone());
+        // Only the detected content type is trusted, not the one declared on upload
+        meta.metadata.insert(
+            resource::CONTENT_TYPE_METADATA_KEY.to_string(),
+            desc.content_type.clone(),
+        );
+
+        // The client's copy is current, so only the meta frame is sent
+        if !args.if_none_match.is_empty() && args.if_none_match == etag {
+            meta.metadata.insert(
+                resource::NOT_MODIFIED_METADATA_KEY.to_string(),
+                true.to_string(),
+            );
+
+            return Ok(Box::pin(VecStream::once(Ok(DownloadResponse {
+                result: Some(download_response::Result::Meta(meta.into())),
+            }))));
+        }
+
+        let range = resource::resolve_range(&desc, args.offset, args.length)?;
+
+        meta.metadata.insert(
+            resource::RANGE_OFFSET_METADATA_KEY.to_string(),
+            range.start.to_string(),
+        );
+        meta.metadata.insert(
+            resource::RANGE_LENGTH_METADATA_KEY.to_string(),
+            (range.end - range.start).to_string(),
+        );
+
         let meta_stream = VecStream::once(Ok(DownloadResponse {
-            result: Some(download_response::Result::Meta(desc.meta.into())),
+            result: Some(download_response::Result::Meta(meta.into())),
         }));
 
-        let stream = resource::read(resource_id).await?.map(|res| match res {
+        let stream = resource::read(&desc, range).await?.map(|res| match res {
             Ok(data) => Ok(DownloadResponse {
                 result: Some(download_response::Result::Data(data.to_vec())),
             }),
@@ -147,6 +351,180 @@ impl Service {
             result: Some(get_resource_meta_response::Result::Meta(desc.meta.into())),
         })
     }
+
+    async fn _delete_resource(
+        &self,
+        request: Request<DeleteResourceRequest>,
+    ) -> Result<DeleteResourceResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let resource_id = ResourceId::try_from(
+            request
+                .into_inner()
+                .resource_id
+                .ok_or(Error::invalid_argument())?,
+        )?;
+
+        // Derived resources are removed together with their origin
+        resource::validate_id(&resource_id)?;
+
+        let desc = resource::get(database, &resource_id)
+            .await?
+            .ok_or(Error::new(ErrorCode::NotFound, "Resource not found"))?;
+
+        if !resource::is_delete_authorized(database, &desc, &user.user_id).await? {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to delete this resource",
+            ));
+        }
+
+        resource::delete(database, &resource_id).await?;
+
+        Ok(DeleteResourceResponse { error: None })
+    }
+
+    async fn _list_resources(
+        &self,
+        request: Request<ListResourcesRequest>,
+    ) -> Result<ListResourcesResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+
+        // Checked for the whole namespace, so neither resources nor cursors leak hidden keys
+        if !resource::is_list_authorized(database, &args.namespace, &user.user_id).await? {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "User has no permission to list these resources",
+            ));
+        }
+
+        let limit = match args.limit {
+            0 => MAX_LIST_LIMIT,
+            limit => limit.min(MAX_LIST_LIMIT),
+        };
+
+        let (resources, next) =
+            resource::get_resources(database, &args.namespace, limit, &args.cursor).await?;
+
+        Ok(ListResourcesResponse {
+            error: None,
+            resources: resources.into_iter().map(to_proto_resource).collect(),
+            next_cursor: next.unwrap_or_default(),
+        })
+    }
+
+    async fn _get_storage_usage(
+        &self,
+        request: Request<GetStorageUsageRequest>,
+    ) -> Result<GetStorageUsageResponse, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+        let args = request.into_inner();
+        let is_admin = user.role >= UserRole::Admin as i32;
+
+        let target = if args.user_id.is_empty() || args.user_id == user.user_id {
+            user
+        } else if is_admin {
+            user::get(database, &args.user_id)
+                .await?
+                .ok_or(Error::new(ErrorCode::NotFound, "User not found"))?
+        } else {
+            return Err(Error::new(
+                ErrorCode::Unauthorized,
+                "Only admins can view the storage usage of other users",
+            ));
+        };
+
+        let channel = if args.channel_id.is_empty() {
+            None
+        } else {
+            let channel = chat::get_channel(database, &args.channel_id)
+                .await?
+                .ok_or(Error::new(ErrorCode::NotFound, "Channel not found"))?;
+
+            if !is_admin && !channel.members.contains_key(&target.user_id) {
+                return Err(Error::new(ErrorCode::Unauthorized, "User not in channel"));
+            }
+
+            Some(StorageUsage {
+                used: quota::get_channel_usage(database, &channel.channel_id).await?,
+                limit: quota::get_channel_limit(database, &channel.channel_id).await?,
+            })
+        };
+
+        Ok(GetStorageUsageResponse {
+            error: None,
+            user: Some(StorageUsage {
+                used: quota::get_user_usage(database, &target.user_id).await?,
+                limit: quota::get_user_limit(database, &target).await?,
+            }),
+            channel,
+        })
+    }
+
+    async fn _set_storage_quota(
+        &self,
+        request: Request<SetStorageQuotaRequest>,
+    ) -> Result<SetStorageQuotaResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Admin).await?;
+        let args = request.into_inner();
+
+        // Exactly one of user and channel has to be given
+        match (args.user_id.is_empty(), args.channel_id.is_empty()) {
+            (false, true) => {
+                if user::get(database, &args.user_id).await?.is_none() {
+                    return Err(Error::new(ErrorCode::NotFound, "User not found"));
+                }
+
+                quota::set_user_limit(database, &args.user_id, args.limit).await?;
+            }
+            (true, false) => {
+                if !chat::channel_exists(database, &args.channel_id).await? {
+                    return Err(Error::new(ErrorCode::NotFound, "Channel not found"));
+                }
+
+                quota::set_channel_limit(database, &args.channel_id, args.limit).await?;
+            }
+            _ => return Err(Error::invalid_argument()),
+        }
+
+        Ok(SetStorageQuotaResponse { error: None })
+    }
+
+    async fn _collect_garbage(
+        &self,
+        request: Request<CollectGarbageRequest>,
+    ) -> Result<CollectGarbageResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Admin).await?;
+        let dry_run = request.into_inner().dry_run;
+
+        let report = gc::collect(database, dry_run).await?;
+
+        Ok(CollectGarbageResponse {
+            error: None,
+            dry_run: report.dry_run,
+            resources: report.resources.into_iter().map(|id| id.into()).collect(),
+            files: report.files,
+            bytes: report.bytes,
+        })
+    }
+}
+
+fn to_proto_resource(desc: ResourceDescriptor) -> Resource {
+    Resource {
+        resource_id: Some(desc.resource_id.into()),
+        meta: Some(desc.meta.into()),
+        user_id: desc.user_id,
+    }
 }
 
 #[tonic::async_trait]
@@ -165,6 +543,64 @@ impl ResourceService for Service {
         Ok(Response::new(resp))
     }
 
+    async fn init_upload(
+        &self,
+        request: Request<InitUploadRequest>,
+    ) -> Result<Response<InitUploadResponse>, Status> {
+        let resp = self
+            ._init_upload(request)
+            .await
+            .unwrap_or_else(|err| InitUploadResponse {
+                result: Some(init_upload_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn upload_chunk(
+        &self,
+        request: Request<Streaming<UploadChunkRequest>>,
+    ) -> Result<Response<UploadChunkResponse>, Status> {
+        let resp = self
+            ._upload_chunk(request)
+            .await
+            .unwrap_or_else(|err| UploadChunkResponse {
+                offset: 0,
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn get_upload_status(
+        &self,
+        request: Request<GetUploadStatusRequest>,
+    ) -> Result<Response<GetUploadStatusResponse>, Status> {
+        let resp = self
+            ._get_upload_status(request)
+            .await
+            .unwrap_or_else(|err| GetUploadStatusResponse {
+                offset: 0,
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn commit_upload(
+        &self,
+        request: Request<CommitUploadRequest>,
+    ) -> Result<Response<CommitUploadResponse>, Status> {
+        let resp = self
+            ._commit_upload(request)
+            .await
+            .unwrap_or_else(|err| CommitUploadResponse {
+                result: Some(commit_upload_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
     type DownloadStream = BoxStream<DownloadResponse>;
 
     async fn download(
@@ -193,4 +629,82 @@ impl ResourceService for Service {
 
         Ok(Response::new(resp))
     }
+
+    async fn delete_resource(
+        &self,
+        request: Request<DeleteResourceRequest>,
+    ) -> Result<Response<DeleteResourceResponse>, Status> {
+        let resp =
+            self._delete_resource(request)
+                .await
+                .unwrap_or_else(|err| DeleteResourceResponse {
+                    error: Some(err.into()),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_resources(
+        &self,
+        request: Request<ListResourcesRequest>,
+    ) -> Result<Response<ListResourcesResponse>, Status> {
+        let resp =
+            self._list_resources(request)
+                .await
+                .unwrap_or_else(|err| ListResourcesResponse {
+                    error: Some(err.into()),
+                    resources: Vec::new(),
+                    next_cursor: String::new(),
+                });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn get_storage_usage(
+        &self,
+        request: Request<GetStorageUsageRequest>,
+    ) -> Result<Response<GetStorageUsageResponse>, Status> {
+        let resp = self
+            ._get_storage_usage(request)
+            .await
+            .unwrap_or_else(|err| GetStorageUsageResponse {
+                error: Some(err.into()),
+                user: None,
+                channel: None,
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn set_storage_quota(
+        &self,
+        request: Request<SetStorageQuotaRequest>,
+    ) -> Result<Response<SetStorageQuotaResponse>, Status> {
+        let resp = self
+            ._set_storage_quota(request)
+            .await
+            .unwrap_or_else(|err| SetStorageQuotaResponse {
+                error: Some(err.into()),
+            });
+
+        Ok(Response::new(resp))
+    }
+
+    async fn collect_garbage(
+        &self,
+        request: Request<CollectGarbageRequest>,
+    ) -> Result<Response<CollectGarbageResponse>, Status> {
+        let resp =
+            self._collect_garbage(request)
+                .await
+                .unwrap_or_else(|err| CollectGarbageResponse {
+                    error: Some(err.into()),
+                    dry_run: false,
+                    resources: Vec::new(),
+                    files: Vec::new(),
+                    bytes: 0,
+                });
+
+        Ok(Response::new(resp))
+    }
 }
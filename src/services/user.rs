@@ -1,16 +1,25 @@
 use crate::error::Error;
+use crate::presence::{PresenceEvent, Status as PresenceStatus};
 use crate::state::ServerState;
-use crate::{auth, user};
+use crate::utils::{SafeStreaming, VecStream};
+use crate::{auth, bot, chat, resource, user, utils};
 use elysium_rust::common::v1::ErrorCode;
 use elysium_rust::user::v1::user_service_server::UserService;
 use elysium_rust::user::v1::{
-    AuthUserRequest, AuthUserResponse, CreateUserRequest, CreateUserResponse, DeleteUserRequest,
-    DeleteUserResponse, GetUserRequest, GetUserResponse, SearchUsersRequest, SearchUsersResponse,
+    AuthUserRequest, AuthUserResponse, CreateBotRequest, CreateBotResponse, CreateUserRequest,
+    CreateUserResponse, DeleteUserRequest, DeleteUserResponse, GetUserRequest, GetUserResponse,
+    PresenceRequest, PresenceResponse, SearchUsersRequest, SearchUsersResponse, TypingIndicator,
     UpdateUserAvatarRequest, UpdateUserAvatarResponse, UpdateUserRequest, UpdateUserResponse,
-    UserRole, auth_user_response, get_user_response,
+    UserPresence, UserRole, auth_user_response, create_bot_response, get_user_response,
+    presence_request, presence_response,
 };
 use elysium_rust::{ResourceId, User};
-use tonic::{Request, Response, Status};
+use std::collections::HashSet;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tonic::codegen::BoxStream;
+use tonic::codegen::tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
 
 pub struct Service {
     state: ServerState,
@@ -56,6 +65,57 @@ impl Service {
         Ok(CreateUserResponse { error: None })
     }
 
+    async fn _create_bot(
+        &self,
+        request: Request<CreateBotRequest>,
+    ) -> Result<CreateBotResponse, Error> {
+        let database = self.state.database();
+
+        auth::verify_role(database, &request, UserRole::Admin).await?;
+
+        let args = request.into_inner();
+
+        if args.commands.iter().any(|command| {
+            bot::parse_command(&format!("/{command}")) != Some((command.as_str(), ""))
+        }) {
+            return Err(Error::new(ErrorCode::InvalidFormat, "Invalid command name"));
+        }
+
+        let secret = bot::build_secret();
+
+        // Bots have no password and authenticate with their token instead
+        user::create(
+            database,
+            User {
+                user_id: args.user_id.clone(),
+                username: args.username,
+                email: String::new(),
+                password: String::new(),
+                role: UserRole::UserUnspecified as i32,
+                icon: resource::build_user_avatar_id(&args.user_id),
+            },
+        )
+        .await?;
+
+        bot::create(
+            database,
+            bot::Bot {
+                user_id: args.user_id.clone(),
+                token_hash: utils::hash_token(&secret),
+                commands: args.commands,
+                created_at: utils::get_timestamp(),
+            },
+        )
+        .await?;
+
+        Ok(CreateBotResponse {
+            result: Some(create_bot_response::Result::Token(format!(
+                "{}.{secret}",
+                args.user_id
+            ))),
+        })
+    }
+
     async fn _delete_user(
         &self,
         request: Request<DeleteUserRequest>,
@@ -145,6 +205,108 @@ impl Service {
 
         Ok(SearchUsersResponse { users, error: None })
     }
+
+    async fn _presence(
+        &self,
+        request: Request<Streaming<PresenceRequest>>,
+    ) -> Result<BoxStream<PresenceResponse>, Error> {
+        let database = self.state.database();
+
+        let user = auth::verify(database, &request).await?;
+
+        // Contacts are all users sharing a channel with this user
+        let channels = chat::get_user_channels(database, &user.user_id).await?;
+        let contacts = channels
+            .iter()
+            .flat_map(|channel| channel.members.keys().cloned())
+            .filter(|member| member != &user.user_id)
+            .collect::<HashSet<_>>();
+        let channels = channels
+            .into_iter()
+            .map(|channel| channel.channel_id)
+            .collect::<HashSet<_>>();
+
+        let presence = self.state.presence().clone();
+        let updates = presence.subscribe();
+
+        presence.connect(&user.user_id);
+
+        let snapshot = contacts
+            .iter()
+            .map(|contact| {
+                Ok(PresenceResponse {
+                    result: Some(presence_response::Result::Presence(UserPresence {
+                        user_id: contact.clone(),
+                        status: to_proto_status(presence.status(contact)) as i32,
+                    })),
+                })
+            })
+            .collect();
+
+        let mut inbound = SafeStreaming::new(request.into_inner());
+        let user_id = user.user_id.clone();
+        let typing_channels = channels.clone();
+
+        tokio::spawn(async move {
+            while let Some(Ok(req)) = inbound.next_safe().await {
+                match req.kind {
+                    Some(presence_request::Kind::Heartbeat(heartbeat)) => presence.heartbeat(
+                        &user_id,
+                        from_proto_status(heartbeat.status),
+                        heartbeat.active,
+                    ),
+
+                    Some(presence_request::Kind::Typing(typing))
+                        if typing_channels.contains(&typing.channel_id) =>
+                    {
+                        presence.typing(&user_id, &typing.channel_id)
+                    }
+
+                    _ => (),
+                }
+            }
+
+            presence.disconnect(&user_id);
+        });
+
+        let user_id = user.user_id;
+        let updates = BroadcastStream::new(updates).filter_map(move |event| {
+            let result = match event {
+                Ok(PresenceEvent::Status { user_id, status }) if contacts.contains(&user_id) => {
+                    presence_response::Result::Presence(UserPresence {
+                        user_id,
+                        status: to_proto_status(status) as i32,
+                    })
+                }
+
+                Ok(PresenceEvent::Typing {
+                    user_id: typing_user,
+                    channel_id,
+                }) if typing_user != user_id && channels.contains(&channel_id) => {
+                    presence_response::Result::Typing(TypingIndicator {
+                        user_id: typing_user,
+                        channel_id,
+                    })
+                }
+
+                Ok(_) => return None,
+
+                Err(BroadcastStreamRecvError::Lagged(missed)) => presence_response::Result::Error(
+                    Error::new(
+                        ErrorCode::Internal,
+                        format!("Missed {missed} presence events"),
+                    )
+                    .into(),
+                ),
+            };
+
+            Some(Ok(PresenceResponse {
+                result: Some(result),
+            }))
+        });
+
+        Ok(Box::pin(VecStream::new(snapshot).chain(updates)))
+    }
 }
 
 #[tonic::async_trait]
@@ -177,6 +339,20 @@ impl UserService for Service {
         Ok(Response::new(resp))
     }
 
+    async fn create_bot(
+        &self,
+        request: Request<CreateBotRequest>,
+    ) -> Result<Response<CreateBotResponse>, Status> {
+        let resp = self
+            ._create_bot(request)
+            .await
+            .unwrap_or_else(|err| CreateBotResponse {
+                result: Some(create_bot_response::Result::Error(err.into())),
+            });
+
+        Ok(Response::new(resp))
+    }
+
     async fn delete_user(
         &self,
         request: Request<DeleteUserRequest>,
@@ -247,4 +423,37 @@ impl UserService for Service {
 
         Ok(Response::new(resp))
     }
+
+    type PresenceStream = BoxStream<PresenceResponse>;
+
+    async fn presence(
+        &self,
+        request: Request<Streaming<PresenceRequest>>,
+    ) -> Result<Response<Self::PresenceStream>, Status> {
+        let resp = self._presence(request).await.unwrap_or_else(|err| {
+            Box::pin(VecStream::once(Ok(PresenceResponse {
+                result: Some(presence_response::Result::Error(err.into())),
+            })))
+        });
+
+        Ok(Response::new(resp))
+    }
+}
+
+fn to_proto_status(status: PresenceStatus) -> elysium_rust::user::v1::PresenceStatus {
+    match status {
+        PresenceStatus::Online => elysium_rust::user::v1::PresenceStatus::Online,
+        PresenceStatus::Idle => elysium_rust::user::v1::PresenceStatus::Idle,
+        PresenceStatus::DoNotDisturb => elysium_rust::user::v1::PresenceStatus::DoNotDisturb,
+        PresenceStatus::Offline => elysium_rust::user::v1::PresenceStatus::Offline,
+    }
+}
+
+fn from_proto_status(status: i32) -> PresenceStatus {
+    match elysium_rust::user::v1::PresenceStatus::try_from(status) {
+        Ok(elysium_rust::user::v1::PresenceStatus::Idle) => PresenceStatus::Idle,
+        Ok(elysium_rust::user::v1::PresenceStatus::DoNotDisturb) => PresenceStatus::DoNotDisturb,
+        Ok(elysium_rust::user::v1::PresenceStatus::Offline) => PresenceStatus::Offline,
+        _ => PresenceStatus::Online,
+    }
 }
@@ -1,18 +1,60 @@
+use crate::bot::Bots;
+use crate::config;
 use crate::database::Database;
+use crate::events::Events;
+use crate::filter::Filters;
+use crate::presence::Presence;
+use crate::ratelimit::RateLimiter;
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct ServerState {
     database: Database,
+    events: Events,
+    presence: Presence,
+    rate_limiter: RateLimiter,
+    bots: Bots,
+    filters: Filters,
 }
 
 impl ServerState {
     pub async fn new() -> Self {
+        let config = config::get();
+
         Self {
             database: Database::new().await,
+            events: Events::new(),
+            presence: Presence::new(
+                Duration::from_secs(config.service_presence_timeout),
+                Duration::from_secs(config.service_presence_idle),
+            ),
+            rate_limiter: RateLimiter::new(),
+            bots: Bots::new(),
+            filters: Filters::new(),
         }
     }
 
     pub fn database(&self) -> &Database {
         &self.database
     }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    pub fn presence(&self) -> &Presence {
+        &self.presence
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub fn bots(&self) -> &Bots {
+        &self.bots
+    }
+
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
 }
@@ -0,0 +1,375 @@
+use crate::config;
+use crate::error::Error;
+use crate::utils::RESOURCE_CHUNK_SIZE;
+use elysium_rust::common::v1::ErrorCode;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path as ObjectPath;
+use object_store::{GetOptions, GetRange, ObjectStore, ObjectStoreExt, WriteMultipart};
+use std::fmt::Debug;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::OnceLock;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tonic::codegen::tokio_stream::{Stream, StreamExt};
+
+pub const STORE_LOCAL: &str = "local";
+pub const STORE_S3: &str = "s3";
+
+/// Directory inside the resource directory holding the blobs, named so no namespace can clash.
+pub const LOCAL_BLOB_DIR: &str = ".blobs";
+
+/// Prefix of all blob objects inside the bucket.
+const S3_BLOB_PREFIX: &str = "blobs";
+
+/// Size of the parts of multipart uploads, S3 requires at least 5 MiB for all but the last.
+const S3_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum number of parts uploaded concurrently.
+const S3_MAX_CONCURRENCY: usize = 4;
+
+static STORE: OnceLock<Box<dyn ResourceStore>> = OnceLock::new();
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, Error>> + Send>>;
+
+pub fn init() {
+    let config = config::get();
+
+    let store: Box<dyn ResourceStore> = match config.service_store.as_str() {
+        STORE_LOCAL => Box::new(LocalStore::new(
+            Path::new(&config.service_resource_dir).join(LOCAL_BLOB_DIR),
+        )),
+        STORE_S3 => Box::new(S3Store::new(
+            AmazonS3Builder::new()
+                .with_endpoint(&config.service_s3_endpoint)
+                .with_region(&config.service_s3_region)
+                .with_bucket_name(&config.service_s3_bucket)
+                .with_access_key_id(&config.service_s3_access_key)
+                .with_secret_access_key(config.s3_secret_key())
+                .with_allow_http(config.service_s3_allow_http)
+                .build()
+                .expect("Failed to build S3 client"),
+        )),
+        other => panic!("Unknown resource store '{other}'"),
+    };
+
+    STORE.set(store).expect("Failed to set resource store");
+}
+
+pub fn get<'a>() -> &'a dyn ResourceStore {
+    STORE.get().expect("Failed to get resource store").as_ref()
+}
+
+/// Storage of blobs, addressed by keys of '/' separated path segments.
+#[tonic::async_trait]
+pub trait ResourceStore: Send + Sync + Debug {
+    async fn exists(&self, key: &str) -> Result<bool, Error>;
+
+    /// Streams the bytes of a blob within `range`, fails with [`ErrorCode::NotFound`] if it does
+    /// not exist.
+    ///
+    /// The range has to be non-empty and within the blob.
+    async fn read(&self, key: &str, range: Range<u64>) -> Result<ByteStream, Error>;
+
+    /// Moves a fully written local file into the store, replacing any existing blob.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), Error>;
+
+    /// Removes a blob, succeeding if it does not exist.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Stores blobs as files below a local directory.
+#[derive(Debug)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[tonic::async_trait]
+impl ResourceStore for LocalStore {
+    async fn exists(&self, key: &str) -> Result<bool, Error> {
+        fs::try_exists(self.path(key)).await.map_err(|e| {
+            tracing::error!("Failed checking blob file: {e}");
+            Error::new(ErrorCode::Internal, "Failed to read resource")
+        })
+    }
+
+    async fn read(&self, key: &str, range: Range<u64>) -> Result<ByteStream, Error> {
+        read_file(&self.path(key), range).await
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), Error> {
+        move_file(path, &self.path(key)).await.map_err(|e| {
+            tracing::error!("Failed moving blob file: {e}");
+            Error::new(ErrorCode::Internal, "Failed to write resource")
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        if let Err(e) = fs::remove_file(self.path(key)).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::error!("Failed removing blob file: {e}");
+            return Err(Error::new(ErrorCode::Internal, "Failed to delete resource"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores blobs as objects in an S3-compatible bucket, e.g. AWS S3 or MinIO.
+///
+/// The bucket must already exist.
+#[derive(Debug)]
+pub struct S3Store {
+    client: AmazonS3,
+}
+
+impl S3Store {
+    pub fn new(client: AmazonS3) -> Self {
+        Self { client }
+    }
+
+    fn path(&self, key: &str) -> ObjectPath {
+        ObjectPath::from(format!("{S3_BLOB_PREFIX}/{key}"))
+    }
+
+    async fn upload(&self, key: &str, path: &Path) -> Result<(), Error> {
+        let mut file = fs::File::open(path).await.map_err(|e| {
+            tracing::error!("Failed opening blob file for upload: {e}");
+            Error::new(ErrorCode::Internal, "Failed to write resource")
+        })?;
+
+        let upload = self
+            .client
+            .put_multipart(&self.path(key))
+            .await
+            .map_err(map_s3_err)?;
+
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, S3_PART_SIZE);
+        let mut buf = vec![0; RESOURCE_CHUNK_SIZE];
+
+        let result = async {
+            loop {
+                let read = file.read(&mut buf).await.map_err(|e| {
+                    tracing::error!("Failed reading blob file for upload: {e}");
+                    Error::new(ErrorCode::Internal, "Failed to write resource")
+                })?;
+
+                if read == 0 {
+                    break;
+                }
+
+                writer
+                    .wait_for_capacity(S3_MAX_CONCURRENCY)
+                    .await
+                    .map_err(map_s3_err)?;
+                writer.write(&buf[..read]);
+            }
+
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                writer.finish().await.map_err(map_s3_err)?;
+                Ok(())
+            }
+            Err(err) => {
+                if let Err(e) = writer.abort().await {
+                    tracing::error!("Failed aborting multipart upload: {e}");
+                }
+
+                Err(err)
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ResourceStore for S3Store {
+    async fn exists(&self, key: &str) -> Result<bool, Error> {
+        match self.client.head(&self.path(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(map_s3_err(e)),
+        }
+    }
+
+    async fn read(&self, key: &str, range: Range<u64>) -> Result<ByteStream, Error> {
+        let result = self
+            .client
+            .get_opts(
+                &self.path(key),
+                GetOptions {
+                    range: Some(GetRange::Bounded(range)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(map_s3_err)?;
+
+        let stream = result.into_stream().map(|res| {
+            res.map(|by| by.to_vec()).map_err(|err| {
+                tracing::error!("Failed reading object: {err}");
+                Error::new(ErrorCode::Internal, "Failed to read resource")
+            })
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), Error> {
+        let result = self.upload(key, path).await;
+
+        if let Err(e) = fs::remove_file(path).await {
+            tracing::error!("Failed removing uploaded blob file: {e}");
+        }
+
+        result
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match self.client.delete(&self.path(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(map_s3_err(e)),
+        }
+    }
+}
+
+/// Streams the bytes of a local file within `range`, fails with [`ErrorCode::NotFound`] if it
+/// does not exist.
+pub async fn read_file(path: &Path, range: Range<u64>) -> Result<ByteStream, Error> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(false)
+        .create(false)
+        .append(false)
+        .open(path)
+        .await
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return Error::new(ErrorCode::NotFound, "Resource data not found");
+            }
+
+            tracing::error!("Failed opening read file: {e}");
+            Error::new(ErrorCode::Internal, "Failed to read resource")
+        })?;
+
+    file.seek(SeekFrom::Start(range.start)).await.map_err(|e| {
+        tracing::error!("Failed seeking read file: {e}");
+        Error::new(ErrorCode::Internal, "Failed to read resource")
+    })?;
+
+    let reader = file.take(range.end - range.start);
+
+    let stream = ReaderStream::with_capacity(reader, RESOURCE_CHUNK_SIZE).map(|res| {
+        res.map_err(|err| {
+            tracing::error!("Failed reading file: {err}");
+            Error::new(ErrorCode::Internal, "Failed to read resource")
+        })
+        .map(|by| by.to_vec())
+    });
+
+    Ok(Box::pin(stream))
+}
+
+/// Renames a file, falling back to copying if both paths are on different file systems.
+pub async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    if fs::rename(from, to).await.is_err() {
+        fs::copy(from, to).await?;
+        fs::remove_file(from).await?;
+    }
+
+    Ok(())
+}
+
+fn map_s3_err(err: object_store::Error) -> Error {
+    match err {
+        object_store::Error::NotFound { .. } => {
+            Error::new(ErrorCode::NotFound, "Resource data not found")
+        }
+        err => {
+            tracing::error!("Failed accessing object store: {err}");
+            Error::new(ErrorCode::Internal, "Failed to access resource storage")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs the MinIO server of `dev/run-minio.sh` with an `elysium` bucket and the
+    /// `MINIO_ROOT_USER`, `MINIO_ROOT_PASSWORD` and optional `MINIO_ADDRESS` it was started with.
+    /// Skipped if the credentials are not set.
+    #[tokio::test]
+    #[ignore]
+    async fn s3_round_trip() {
+        let (Ok(user), Ok(password)) = (
+            std::env::var("MINIO_ROOT_USER"),
+            std::env::var("MINIO_ROOT_PASSWORD"),
+        ) else {
+            eprintln!("Skipping S3 round trip, MINIO_ROOT_USER or MINIO_ROOT_PASSWORD is not set");
+            return;
+        };
+        let address =
+            std::env::var("MINIO_ADDRESS").unwrap_or_else(|_| "127.0.0.1:9000".to_string());
+
+        let store = S3Store::new(
+            AmazonS3Builder::new()
+                .with_endpoint(format!("http://{address}"))
+                .with_region("us-east-1")
+                .with_bucket_name("elysium")
+                .with_access_key_id(user)
+                .with_secret_access_key(password)
+                .with_allow_http(true)
+                .build()
+                .expect("Failed to build S3 client"),
+        );
+
+        let key = format!("test/{}", nanoid::nanoid!());
+        let data: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+        let path = std::env::temp_dir().join(format!("elysium-s3-{}", nanoid::nanoid!()));
+        fs::write(&path, &data).await.unwrap();
+
+        store.put_file(&key, &path).await.unwrap();
+        assert!(!fs::try_exists(&path).await.unwrap());
+        assert!(store.exists(&key).await.unwrap());
+
+        let mut read = Vec::new();
+        let mut stream = store.read(&key, 1000..51000).await.unwrap();
+        while let Some(chunk) = stream.next().await {
+            read.extend(chunk.unwrap());
+        }
+        assert_eq!(read, data[1000..51000]);
+
+        store.delete(&key).await.unwrap();
+        assert!(!store.exists(&key).await.unwrap());
+        assert_eq!(
+            store.read(&key, 0..1).await.err().unwrap().code(),
+            ErrorCode::NotFound
+        );
+
+        // Deleting twice succeeds
+        store.delete(&key).await.unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use crate::error::Error;
+use crate::events::ChannelEvent;
+use crate::state::ServerState;
+use crate::{chat, config, gc, moderation, resource, upload, utils, webhook};
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Message, Timestamp};
+use std::time::Duration;
+
+/// Maximum number of expired messages removed in one pass.
+const EXPIRY_BATCH_SIZE: usize = 100;
+
+/// Maximum number of scheduled messages delivered in one pass.
+const SCHEDULE_BATCH_SIZE: usize = 100;
+
+/// Delay before a scheduled message which failed to send for a temporary reason is retried.
+const SCHEDULE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of deliveries of a scheduled message attempted before it is dropped.
+const SCHEDULE_MAX_ATTEMPTS: u32 = 5;
+
+/// Maximum number of webhook deliveries attempted in one pass.
+const WEBHOOK_BATCH_SIZE: usize = 50;
+
+/// Interval between presence timeout checks.
+const PRESENCE_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of abandoned uploads removed in one pass.
+const UPLOAD_BATCH_SIZE: usize = 100;
+
+/// Interval between checks for abandoned uploads.
+const UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Maximum number of unreferenced blobs removed in one pass.
+const BLOB_BATCH_SIZE: usize = 100;
+
+/// Interval between checks for unreferenced blobs.
+const BLOB_SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Time a blob stays around without references, so freshly written blobs can be registered.
+const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Interval between removals of finished rate limit windows.
+const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Spawns all background tasks of the server.
+pub fn spawn(state: &ServerState) {
+    tokio::spawn(expire_messages(state.clone()));
+    tokio::spawn(deliver_scheduled(state.clone()));
+    tokio::spawn(sweep_presence(state.clone()));
+    tokio::spawn(sweep_rate_limits(state.clone()));
+    tokio::spawn(deliver_webhooks(state.clone()));
+    tokio::spawn(reload_filters(state.clone()));
+    tokio::spawn(sweep_uploads(state.clone()));
+    tokio::spawn(sweep_blobs(state.clone()));
+    tokio::spawn(collect_garbage(state.clone()));
+}
+
+/// Removes orphaned resources and files, unless collection is configured to be on demand only.
+async fn collect_garbage(state: ServerState) {
+    let period = config::get().service_gc_interval;
+
+    if period == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(period));
+
+    loop {
+        interval.tick().await;
+
+        match gc::collect(state.database(), false).await {
+            Ok(report) if report.resources.is_empty() && report.files.is_empty() => {}
+            Ok(report) => tracing::info!(
+                "Collected {} orphaned resources and {} orphaned files, freeing {} bytes",
+                report.resources.len(),
+                report.files.len(),
+                report.bytes
+            ),
+            Err(err) => tracing::error!("Failed collecting orphaned resources: {err}"),
+        }
+    }
+}
+
+/// Removes blobs which are no longer referenced by any resource.
+async fn sweep_blobs(state: ServerState) {
+    let mut interval = tokio::time::interval(BLOB_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let before = Timestamp {
+            millis: utils::get_timestamp()
+                .millis
+                .saturating_sub(BLOB_GRACE_PERIOD.as_millis() as u64),
+        };
+
+        match resource::sweep_blobs(state.database(), before, BLOB_BATCH_SIZE).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("Removed {removed} unreferenced blobs"),
+            Err(err) => tracing::error!("Failed removing unreferenced blobs: {err}"),
+        }
+    }
+}
+
+/// Removes upload sessions which stopped receiving data, together with their staged data.
+async fn sweep_uploads(state: ServerState) {
+    let mut interval = tokio::time::interval(UPLOAD_SWEEP_INTERVAL);
+    let database = state.database();
+
+    loop {
+        interval.tick().await;
+
+        let ttl = config::get().service_upload_session_ttl * 1000;
+        let before = Timestamp {
+            millis: utils::get_timestamp().millis.saturating_sub(ttl),
+        };
+
+        let stale = match upload::get_stale_sessions(database, before, UPLOAD_BATCH_SIZE).await {
+            Ok(stale) => stale,
+            Err(err) => {
+                tracing::error!("Failed fetching abandoned uploads: {err}");
+                continue;
+            }
+        };
+
+        for session in stale {
+            if let Err(err) = upload::delete_session(database, &session.upload_id).await {
+                tracing::error!("Failed removing abandoned upload: {err}");
+            }
+        }
+    }
+}
+
+/// Reloads the content filter rules, picking up rules changed outside of this server.
+async fn reload_filters(state: ServerState) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config::get().service_filter_interval));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = state.filters().reload(state.database()).await {
+            tracing::error!("Failed reloading content filter rules: {err}");
+        }
+    }
+}
+
+/// Forgets rate limit windows that are over, unanswered bot commands and event channels without
+/// subscribers.
+async fn sweep_rate_limits(state: ServerState) {
+    let mut interval = tokio::time::interval(RATE_LIMIT_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        state.rate_limiter().sweep();
+        state.bots().sweep();
+        state.events().sweep();
+    }
+}
+
+/// Delivers queued webhook events, retrying failed deliveries with back-off.
+async fn deliver_webhooks(state: ServerState) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config::get().service_webhook_interval));
+    let database = state.database();
+
+    loop {
+        interval.tick().await;
+
+        let due =
+            match webhook::get_due_deliveries(database, utils::get_timestamp(), WEBHOOK_BATCH_SIZE)
+                .await
+            {
+                Ok(due) => due,
+                Err(err) => {
+                    tracing::error!("Failed reading due webhook deliveries: {err}");
+                    continue;
+                }
+            };
+
+        for delivery in due {
+            if let Err(err) = webhook::deliver(database, delivery).await {
+                tracing::error!("Failed recording webhook delivery: {err}");
+            }
+        }
+    }
+}
+
+/// Marks users as idle or offline once their heartbeats stop.
+async fn sweep_presence(state: ServerState) {
+    let mut interval = tokio::time::interval(PRESENCE_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        state.presence().sweep();
+    }
+}
+
+/// Removes messages once their lifetime is over.
+///
+/// Expiry times are stored on the messages themselves, so anything that expired while the
+/// server was down is removed on the first pass after startup.
+async fn expire_messages(state: ServerState) {
+    let interval = Duration::from_secs(config::get().service_expiry_interval);
+    let database = state.database();
+
+    loop {
+        let now = utils::get_timestamp();
+
+        match chat::get_expired(database, now.clone(), EXPIRY_BATCH_SIZE).await {
+            Ok(messages) => {
+                let batch_full = messages.len() == EXPIRY_BATCH_SIZE;
+                let mut failed = false;
+
+                for message in messages {
+                    match chat::delete_message(database, &message.message_id).await {
+                        Ok(Some(pin)) => state.events().publish(ChannelEvent::MessageUnpinned {
+                            channel_id: pin.channel_id,
+                            message_id: pin.message_id,
+                        }),
+                        Ok(None) => {}
+                        Err(err) => {
+                            tracing::error!("Failed deleting expired message: {err}");
+                            failed = true;
+                            continue;
+                        }
+                    }
+
+                    state.events().publish(ChannelEvent::MessageDeleted {
+                        channel_id: message.channel_id,
+                        message_id: message.message_id,
+                    });
+                }
+
+                // Failed messages are still due and would be read again right away
+                if failed {
+                    tokio::time::sleep(interval).await;
+                    continue;
+                }
+
+                if batch_full {
+                    continue;
+                }
+            }
+
+            Err(err) => tracing::error!("Failed reading expired messages: {err}"),
+        }
+
+        let delay = match chat::next_expiry(database).await {
+            Ok(Some(next)) => Duration::from_millis(next.millis.saturating_sub(now.millis)),
+            Ok(None) => interval,
+            Err(err) => {
+                tracing::error!("Failed reading next message expiry: {err}");
+                interval
+            }
+        };
+
+        tokio::time::sleep(delay.min(interval)).await;
+    }
+}
+
+/// Sends scheduled messages once they are due.
+///
+/// Channel permissions are checked again on delivery, since they might have changed after the
+/// message was scheduled. Deliveries failing for a temporary reason are retried a few times
+/// before the message is dropped.
+async fn deliver_scheduled(state: ServerState) {
+    let interval = Duration::from_secs(config::get().service_schedule_interval);
+    let database = state.database();
+
+    loop {
+        let now = utils::get_timestamp();
+
+        match chat::get_due_scheduled(database, now.clone(), SCHEDULE_BATCH_SIZE).await {
+            Ok(due) => {
+                let batch_full = due.len() == SCHEDULE_BATCH_SIZE;
+
+                for scheduled in due {
+                    match deliver(&state, scheduled.clone()).await {
+                        // Temporary failures such as an unavailable database pass, so the message is kept
+                        Err(err)
+                            if is_temporary(&err)
+                                && scheduled.attempts + 1 < SCHEDULE_MAX_ATTEMPTS =>
+                        {
+                            tracing::warn!(
+                                "Postponing scheduled message '{}': {err}",
+                                scheduled.schedule_id
+                            );
+
+                            // Slow mode tells how long to wait
+                            let delay = err.retry_after().unwrap_or(SCHEDULE_RETRY_DELAY);
+                            let send_at = Timestamp {
+                                millis: utils::get_timestamp().millis + delay.as_millis() as u64,
+                            };
+
+                            if let Err(err) =
+                                chat::postpone_scheduled(database, &scheduled.schedule_id, send_at)
+                                    .await
+                            {
+                                tracing::error!("Failed postponing scheduled message: {err}");
+                            }
+
+                            continue;
+                        }
+                        Err(err) => tracing::warn!(
+                            "Dropping scheduled message '{}': {err}",
+                            scheduled.schedule_id
+                        ),
+                        // Removed from the schedule together with sending the message
+                        Ok(()) => continue,
+                    }
+
+                    if let Err(err) = chat::cancel_scheduled(database, &scheduled.schedule_id).await
+                    {
+                        tracing::error!("Failed removing dropped scheduled message: {err}");
+                    }
+                }
+
+                if batch_full {
+                    continue;
+                }
+            }
+
+            Err(err) => tracing::error!("Failed reading due scheduled messages: {err}"),
+        }
+
+        let delay = match chat::next_scheduled(database).await {
+            Ok(Some(next)) => Duration::from_millis(next.millis.saturating_sub(now.millis)),
+            Ok(None) => interval,
+            Err(err) => {
+                tracing::error!("Failed reading next scheduled message: {err}");
+                interval
+            }
+        };
+
+        tokio::time::sleep(delay.min(interval)).await;
+    }
+}
+
+/// Checks whether a failed delivery may succeed later, unlike e.g. a lost permission, a mute or
+/// a deleted channel.
+fn is_temporary(err: &Error) -> bool {
+    matches!(
+        err.code(),
+        ErrorCode::Internal | ErrorCode::Unspecified | ErrorCode::RateLimited
+    )
+}
+
+async fn deliver(state: &ServerState, scheduled: chat::ScheduledMessage) -> Result<(), Error> {
+    let database = state.database();
+
+    let perm =
+        chat::get_channel_member_perm(database, &scheduled.channel_id, &scheduled.user_id).await?;
+
+    if !chat::can_send(perm) {
+        return Err(Error::new(
+            ErrorCode::Unauthorized,
+            "User has no permission to send messages",
+        ));
+    }
+
+    moderation::check_can_post(database, &scheduled.channel_id, &scheduled.user_id).await?;
+    moderation::check_banned(database, &scheduled.user_id).await?;
+
+    resource::validate_attachments(
+        database,
+        &scheduled.channel_id,
+        &scheduled.user_id,
+        &scheduled.content.attachments,
+    )
+    .await?;
+
+    let mut content = scheduled.content;
+    content.created_at = utils::get_timestamp();
+
+    chat::post_scheduled(
+        state,
+        Message {
+            message_id: chat::build_message_id(),
+            user_id: scheduled.user_id,
+            channel_id: scheduled.channel_id,
+            content,
+        },
+        scheduled.ttl,
+        &scheduled.schedule_id,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use crate::database::Database;
+use crate::error::Error;
+use crate::resource::{self, ResourceDescriptor};
+use crate::{config, media, utils};
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{ResourceId, ResourceMeta, Timestamp};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use surrealdb::types::SurrealValue;
+use tokio::fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+pub const ID_LENGTH: usize = 16;
+
+/// Uploads with a chunk currently being written to their staged data.
+static WRITING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+pub fn build_upload_id() -> String {
+    nanoid::nanoid!(ID_LENGTH)
+}
+
+pub async fn create_session(
+    database: &Database,
+    session: UploadSession,
+) -> Result<UploadSession, Error> {
+    let session: Option<UploadSession> = database
+        .create(("upload_session", session.upload_id.as_str()))
+        .content(session)
+        .await?;
+
+    session.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to create upload session",
+    ))
+}
+
+pub async fn get_session(
+    database: &Database,
+    upload_id: &str,
+) -> Result<Option<UploadSession>, Error> {
+    let session: Option<UploadSession> = database.select(("upload_session", upload_id)).await?;
+
+    Ok(session)
+}
+
+/// Removes an upload session together with its staged data.
+pub async fn delete_session(database: &Database, upload_id: &str) -> Result<(), Error> {
+    let _: Option<UploadSession> = database.delete(("upload_session", upload_id)).await?;
+
+    if let Err(e) = fs::remove_file(build_staging_path(upload_id)).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::error!("Failed removing staged upload: {e}");
+        return Err(Error::new(
+            ErrorCode::Internal,
+            "Failed to remove staged upload",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns upload sessions which received no data since `before`.
+pub async fn get_stale_sessions(
+    database: &Database,
+    before: Timestamp,
+    limit: usize,
+) -> Result<Vec<UploadSession>, Error> {
+    let sessions: Vec<UploadSession> = database
+        .query(
+            r#"
+SELECT *
+FROM upload_session
+WHERE updated_at.millis < $before
+LIMIT $limit;
+"#,
+        )
+        .bind(("before", before.millis))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(sessions)
+}
+
+/// Appends a chunk to the staged data after verifying its offset and SHA-256 checksum.
+pub async fn write_chunk(
+    database: &Database,
+    session: &mut UploadSession,
+    offset: u64,
+    data: &[u8],
+    checksum: &str,
+) -> Result<(), Error> {
+    verify_chunk(session, offset, data, checksum)?;
+
+    let Some(_guard) = WritingGuard::acquire(&session.upload_id) else {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Another chunk of this upload is being written",
+        ));
+    };
+
+    // The session might have advanced since it was read, and the staged data must not be touched then
+    if get_session(database, &session.upload_id)
+        .await?
+        .is_none_or(|current| current.offset != session.offset)
+    {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Upload offset changed while writing the chunk",
+        ));
+    }
+
+    let map_err = |e: std::io::Error| {
+        tracing::error!("Failed writing staged upload: {e}");
+        Error::new(ErrorCode::Internal, "Failed to write upload chunk")
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(build_staging_path(&session.upload_id))
+        .await
+        .map_err(map_err)?;
+
+    // Drop anything past the last verified chunk, e.g. from a write interrupted by a crash
+    file.set_len(session.offset).await.map_err(map_err)?;
+    file.seek(SeekFrom::Start(session.offset))
+        .await
+        .map_err(map_err)?;
+    file.write_all(data).await.map_err(map_err)?;
+    file.sync_data().await.map_err(map_err)?;
+
+    let expected = session.offset;
+    let offset = expected + data.len() as u64;
+    let updated_at = utils::get_timestamp();
+
+    // Only advance from the offset this chunk was written at, so a concurrent chunk can't be lost
+    let updated: Option<UploadSession> = database
+        .query(
+            r#"
+UPDATE ONLY type::record('upload_session', $id)
+SET offset = $offset, updated_at = $updated_at
+WHERE offset = $expected;
+"#,
+        )
+        .bind(("id", session.upload_id.clone()))
+        .bind(("offset", offset))
+        .bind(("updated_at", updated_at.clone()))
+        .bind(("expected", expected))
+        .await?
+        .take(0)?;
+
+    if updated.is_none() {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Upload offset changed while writing the chunk",
+        ));
+    }
+
+    session.offset = offset;
+    session.updated_at = updated_at;
+
+    Ok(())
+}
+
+/// Checks that a chunk continues the upload at its current offset, fits into the declared size
+/// and matches its SHA-256 checksum.
+fn verify_chunk(
+    session: &UploadSession,
+    offset: u64,
+    data: &[u8],
+    checksum: &str,
+) -> Result<(), Error> {
+    if offset != session.offset {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!(
+                "Chunk offset {offset} does not match upload offset {}",
+                session.offset
+            ),
+        ));
+    }
+
+    if session.offset + data.len() as u64 > session.meta.size as u64 {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Upload is larger than its declared size",
+        ));
+    }
+
+    if !hex::encode(Sha256::digest(data)).eq_ignore_ascii_case(checksum) {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Chunk checksum does not match",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Moves the staged data into the blob store and registers the resource.
+pub async fn commit(
+    database: &Database,
+    session: UploadSession,
+) -> Result<ResourceDescriptor, Error> {
+    if session.meta.size as u64 != session.offset {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            format!(
+                "Upload is incomplete, got {} of {} bytes",
+                session.offset, session.meta.size
+            ),
+        ));
+    }
+
+    let staging = build_staging_path(&session.upload_id);
+
+    let written = resource::inspect_file(&staging).await?;
+
+    resource::store(database, &staging, &written).await?;
+
+    let desc = media::save_upload(
+        database,
+        session.resource_id,
+        session.meta,
+        session.user_id,
+        written,
+    )
+    .await?;
+
+    delete_session(database, &session.upload_id).await?;
+
+    Ok(desc)
+}
+
+/// Marks an upload as being written to until dropped.
+struct WritingGuard {
+    upload_id: String,
+}
+
+impl WritingGuard {
+    fn acquire(upload_id: &str) -> Option<Self> {
+        let mut writing = WRITING.lock().unwrap();
+
+        writing.insert(upload_id.to_string()).then(|| Self {
+            upload_id: upload_id.to_string(),
+        })
+    }
+}
+
+impl Drop for WritingGuard {
+    fn drop(&mut self) {
+        WRITING.lock().unwrap().remove(&self.upload_id);
+    }
+}
+
+fn build_staging_path(upload_id: &str) -> PathBuf {
+    Path::new(&config::get().service_staging_dir).join(upload_id)
+}
+
+/// An upload in progress, with its data staged until the upload is committed.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct UploadSession {
+    pub upload_id: String,
+    pub resource_id: ResourceId,
+    pub meta: ResourceMeta,
+    pub user_id: String,
+    /// Number of bytes received and verified so far.
+    pub offset: u64,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"chunk";
+
+    fn session(size: i32, offset: u64) -> UploadSession {
+        UploadSession {
+            upload_id: build_upload_id(),
+            resource_id: ResourceId::default(),
+            meta: ResourceMeta {
+                size,
+                ..Default::default()
+            },
+            user_id: String::new(),
+            offset,
+            created_at: Timestamp::default(),
+            updated_at: Timestamp::default(),
+        }
+    }
+
+    fn checksum(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn verify_chunk_accepts_next_chunk() {
+        assert!(verify_chunk(&session(10, 5), 5, DATA, &checksum(DATA)).is_ok());
+        assert!(verify_chunk(&session(10, 0), 0, DATA, &checksum(DATA).to_uppercase()).is_ok());
+    }
+
+    #[test]
+    fn verify_chunk_rejects_offset_mismatch() {
+        for offset in [0, 4, 6] {
+            assert_eq!(
+                verify_chunk(&session(20, 5), offset, DATA, &checksum(DATA))
+                    .unwrap_err()
+                    .code(),
+                ErrorCode::InvalidFormat
+            );
+        }
+    }
+
+    #[test]
+    fn verify_chunk_rejects_checksum_mismatch() {
+        assert_eq!(
+            verify_chunk(&session(10, 0), 0, DATA, &checksum(b"other"))
+                .unwrap_err()
+                .code(),
+            ErrorCode::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn verify_chunk_rejects_oversize_chunk() {
+        assert_eq!(
+            verify_chunk(&session(9, 5), 5, DATA, &checksum(DATA))
+                .unwrap_err()
+                .code(),
+            ErrorCode::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn writing_guard_is_exclusive() {
+        let upload_id = build_upload_id();
+
+        let guard = WritingGuard::acquire(&upload_id);
+        assert!(guard.is_some());
+        assert!(WritingGuard::acquire(&upload_id).is_none());
+
+        drop(guard);
+        assert!(WritingGuard::acquire(&upload_id).is_some());
+    }
+}
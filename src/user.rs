@@ -1,50 +1,34 @@
 use crate::database::Database;
 use crate::error::Error;
 use crate::resource::ResourceDescriptor;
-use crate::utils::VecStream;
-use crate::{auth, config, resource, utils};
+use crate::{auth, bot, config, resource, utils};
 use elysium_rust::common::v1::ErrorCode;
 use elysium_rust::user::v1::{UserProfile, UserRole};
 use elysium_rust::{ResourceMeta, User};
-use tonic::codegen::tokio_stream::StreamExt;
 
 pub async fn create(database: &Database, user: User) -> Result<(), Error> {
     if exists(database, user.user_id.as_str()).await? {
         return Err(Error::new(ErrorCode::AlreadyExists, "User already exists"));
     }
 
-    // Read and write default user icon
+    // Alias the default icon as user icon, its blob is shared by all users
     {
-        let mut icon_stream = resource::read(elysium_rust::DEFAULT_USER_ICON.clone()).await?;
+        let written = resource::builtin_blob(database, &elysium_rust::DEFAULT_USER_ICON).await?;
 
-        let mut chunks: Vec<Result<Vec<u8>, Error>> = Vec::new();
-        let mut length = 0;
-
-        while let Some(chunk) = icon_stream.next().await {
-            let chunk = chunk?;
-            length += chunk.len();
-            chunks.push(Ok(chunk));
-        }
-
-        let icon_id = resource::build_user_avatar_id(&user.user_id);
-
-        // Create user icon
         resource::create(
             database,
-            ResourceDescriptor {
-                resource_id: icon_id.clone(),
-                meta: ResourceMeta {
-                    size: length as i32,
+            ResourceDescriptor::written(
+                resource::build_user_avatar_id(&user.user_id),
+                ResourceMeta {
+                    size: 0,
                     timestamp: utils::get_timestamp(),
                     metadata: Default::default(),
                 },
-                user_id: user.user_id.clone(),
-            },
+                user.user_id.clone(),
+                written,
+            )?,
         )
         .await?;
-
-        // Upload default user icon
-        resource::write(icon_id, VecStream::new(chunks)).await?;
     }
 
     let _: Option<User> = database
@@ -58,6 +42,7 @@ pub async fn create(database: &Database, user: User) -> Result<(), Error> {
 pub async fn delete(database: &Database, userid: &str) -> Result<(), Error> {
     if exists(database, userid).await? {
         let _: Option<User> = database.delete(("user", userid)).await?;
+        bot::delete(database, userid).await?;
 
         Ok(())
     } else {
@@ -1,6 +1,7 @@
 use crate::error::Error;
 use elysium_rust::Timestamp;
 use elysium_rust::common::v1::ErrorCode;
+use sha2::{Digest, Sha256};
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -25,6 +26,13 @@ pub fn is_valid_file_name(s: &str) -> bool {
         .all(|c| c.is_alphanumeric() || c == '-' || c == '.' || c == '_')
 }
 
+/// Hashes a high-entropy token secret for storage.
+///
+/// Unlike passwords, random tokens don't need a slow hash.
+pub fn hash_token(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
 pub fn get_timestamp() -> Timestamp {
     Timestamp {
         millis: SystemTime::now()
@@ -0,0 +1,747 @@
+use crate::config;
+use crate::database::Database;
+use crate::error::Error;
+use crate::events::ChannelEvent;
+use crate::utils;
+use elysium_rust::common::v1::ErrorCode;
+use elysium_rust::{Message, Timestamp};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde_json::json;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use surrealdb::types::SurrealValue;
+
+pub const ID_LENGTH: usize = 16;
+
+/// Length of generated signing secrets.
+pub const SECRET_LENGTH: usize = 32;
+
+/// Delay before the first retry of a failed delivery in seconds, doubled on every attempt.
+pub const RETRY_BASE_DELAY: u64 = 10;
+
+/// Upper bound of the retry delay in seconds.
+pub const RETRY_MAX_DELAY: u64 = 60 * 60;
+
+pub const EVENT_MESSAGE_CREATED: &str = "message.created";
+pub const EVENT_MESSAGE_UPDATED: &str = "message.updated";
+pub const EVENT_MESSAGE_DELETED: &str = "message.deleted";
+pub const EVENT_MEMBER_CHANGED: &str = "member.changed";
+
+pub const EVENTS: [&str; 4] = [
+    EVENT_MESSAGE_CREATED,
+    EVENT_MESSAGE_UPDATED,
+    EVENT_MESSAGE_DELETED,
+    EVENT_MEMBER_CHANGED,
+];
+
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_FAILED: &str = "failed";
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+pub async fn create(database: &Database, webhook: Webhook) -> Result<Webhook, Error> {
+    if webhook
+        .events
+        .iter()
+        .any(|event| !EVENTS.contains(&event.as_str()))
+    {
+        return Err(Error::new(
+            ErrorCode::InvalidFormat,
+            "Unknown webhook event",
+        ));
+    }
+
+    check_url(&webhook.url).await?;
+
+    let webhook: Option<Webhook> = database
+        .create(("webhook", webhook.webhook_id.as_str()))
+        .content(webhook)
+        .await?;
+
+    webhook.ok_or(Error::new(ErrorCode::Internal, "Failed to create webhook"))
+}
+
+pub async fn get(database: &Database, webhook_id: &str) -> Result<Option<Webhook>, Error> {
+    let webhook: Option<Webhook> = database.select(("webhook", webhook_id)).await?;
+
+    Ok(webhook)
+}
+
+pub async fn delete(database: &Database, webhook_id: &str) -> Result<(), Error> {
+    let webhook: Option<Webhook> = database.delete(("webhook", webhook_id)).await?;
+
+    if webhook.is_none() {
+        return Err(Error::new(ErrorCode::NotFound, "Webhook not found"));
+    }
+
+    database
+        .query("DELETE webhook_delivery WHERE webhook_id = $webhook;")
+        .bind(("webhook", webhook_id.to_string()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+pub async fn get_channel_webhooks(
+    database: &Database,
+    channel_id: &str,
+) -> Result<Vec<Webhook>, Error> {
+    let webhooks: Vec<Webhook> = database
+        .query("SELECT * FROM webhook WHERE channel_id = $channel;")
+        .bind(("channel", channel_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(webhooks)
+}
+
+pub async fn get_deliveries(
+    database: &Database,
+    webhook_id: &str,
+    limit: u32,
+) -> Result<Vec<Delivery>, Error> {
+    let deliveries: Vec<Delivery> = database
+        .query(
+            r#"
+SELECT *
+FROM webhook_delivery
+WHERE webhook_id = $webhook
+ORDER BY created_at.millis DESC
+LIMIT $limit;
+"#,
+        )
+        .bind(("webhook", webhook_id.to_string()))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(deliveries)
+}
+
+/// Builds a delivery of the event to every webhook of its channel that subscribed to it.
+///
+/// The deliveries have to be created in the same transaction that persists the event, so no
+/// event is lost if the server stops in between.
+pub async fn build_deliveries(
+    database: &Database,
+    event: &ChannelEvent,
+) -> Result<Vec<Delivery>, Error> {
+    let Some((name, payload)) = build_payload(event) else {
+        return Ok(Vec::new());
+    };
+
+    let now = utils::get_timestamp();
+    let mut deliveries = Vec::new();
+
+    for webhook in get_channel_webhooks(database, event.channel_id()).await? {
+        if !webhook.events.iter().any(|e| e == name) {
+            continue;
+        }
+
+        deliveries.push(Delivery {
+            delivery_id: nanoid::nanoid!(ID_LENGTH),
+            webhook_id: webhook.webhook_id,
+            event: name.to_string(),
+            payload: payload.clone(),
+            status: DELIVERY_PENDING.to_string(),
+            attempts: 0,
+            last_error: String::new(),
+            created_at: now.clone(),
+            next_attempt: now.clone(),
+        });
+    }
+
+    Ok(deliveries)
+}
+
+/// Returns up to `limit` pending deliveries that are due at `now`.
+pub async fn get_due_deliveries(
+    database: &Database,
+    now: Timestamp,
+    limit: usize,
+) -> Result<Vec<Delivery>, Error> {
+    let deliveries: Vec<Delivery> = database
+        .query(
+            r#"
+SELECT *
+FROM webhook_delivery
+WHERE status = $status AND next_attempt.millis <= $now
+ORDER BY next_attempt.millis ASC
+LIMIT $limit;
+"#,
+        )
+        .bind(("status", DELIVERY_PENDING))
+        .bind(("now", now.millis))
+        .bind(("limit", limit))
+        .await?
+        .take(0)?;
+
+    Ok(deliveries)
+}
+
+/// Attempts to deliver the event and records the outcome, scheduling a retry on failure.
+pub async fn deliver(database: &Database, mut delivery: Delivery) -> Result<(), Error> {
+    let config = config::get();
+
+    let result = match get(database, &delivery.webhook_id).await? {
+        Some(webhook) => send(&webhook, &delivery).await,
+        None => Err("Webhook was deleted".to_string()),
+    };
+
+    delivery.attempts += 1;
+
+    match result {
+        Ok(()) => {
+            delivery.status = DELIVERY_DELIVERED.to_string();
+            delivery.last_error = String::new();
+        }
+
+        Err(err) => {
+            delivery.last_error = err;
+
+            if delivery.attempts >= config.service_webhook_max_attempts {
+                delivery.status = DELIVERY_FAILED.to_string();
+            } else {
+                let delay = RETRY_BASE_DELAY
+                    .saturating_mul(1 << (delivery.attempts - 1).min(16))
+                    .min(RETRY_MAX_DELAY);
+
+                delivery.next_attempt = Timestamp {
+                    millis: utils::get_timestamp().millis + delay * 1000,
+                };
+            }
+        }
+    }
+
+    let _: Option<Delivery> = database
+        .update(("webhook_delivery", delivery.delivery_id.as_str()))
+        .content(delivery)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn create_incoming(
+    database: &Database,
+    incoming: IncomingWebhook,
+) -> Result<IncomingWebhook, Error> {
+    let incoming: Option<IncomingWebhook> = database
+        .create(("incoming_webhook", incoming.webhook_id.as_str()))
+        .content(incoming)
+        .await?;
+
+    incoming.ok_or(Error::new(
+        ErrorCode::Internal,
+        "Failed to create incoming webhook",
+    ))
+}
+
+pub async fn get_incoming(
+    database: &Database,
+    webhook_id: &str,
+) -> Result<Option<IncomingWebhook>, Error> {
+    let incoming: Option<IncomingWebhook> =
+        database.select(("incoming_webhook", webhook_id)).await?;
+
+    Ok(incoming)
+}
+
+pub async fn delete_incoming(database: &Database, webhook_id: &str) -> Result<(), Error> {
+    let incoming: Option<IncomingWebhook> =
+        database.delete(("incoming_webhook", webhook_id)).await?;
+
+    incoming.map(|_| ()).ok_or(Error::new(
+        ErrorCode::NotFound,
+        "Incoming webhook not found",
+    ))
+}
+
+pub async fn get_channel_incoming(
+    database: &Database,
+    channel_id: &str,
+) -> Result<Vec<IncomingWebhook>, Error> {
+    let incoming: Vec<IncomingWebhook> = database
+        .query("SELECT * FROM incoming_webhook WHERE channel_id = $channel;")
+        .bind(("channel", channel_id.to_string()))
+        .await?
+        .take(0)?;
+
+    Ok(incoming)
+}
+
+/// Resolves an incoming webhook token of the form `<webhook_id>.<secret>`.
+pub async fn verify_incoming(database: &Database, token: &str) -> Result<IncomingWebhook, Error> {
+    let invalid = || Error::new(ErrorCode::Unauthorized, "Invalid webhook token");
+
+    let (webhook_id, secret) = token.split_once('.').ok_or_else(invalid)?;
+
+    let incoming = get_incoming(database, webhook_id)
+        .await?
+        .ok_or_else(invalid)?;
+
+    if utils::hash_token(secret) != incoming.secret_hash {
+        return Err(invalid());
+    }
+
+    Ok(incoming)
+}
+
+/// User ID that messages of an incoming webhook are attributed to.
+///
+/// Contains no `.`, since tokens and user resource namespaces use it as a separator.
+pub fn build_incoming_user_id(webhook_id: &str) -> String {
+    format!("webhook-{webhook_id}")
+}
+
+/// Computes the hex encoded HMAC-SHA256 signature of a delivery.
+pub fn sign(secret: &str, timestamp: u64, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks that a webhook URL uses HTTP or HTTPS and only resolves to public addresses, unless
+/// its host is in the configured allowlist.
+pub async fn check_url(url: &str) -> Result<(), Error> {
+    let invalid = |message: &str| Error::new(ErrorCode::InvalidFormat, message);
+
+    let url = Url::parse(url).map_err(|_| invalid("Invalid webhook URL"))?;
+
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(invalid("Webhook URL must use HTTP or HTTPS"));
+    }
+
+    // IPv6 hosts of URLs are enclosed in brackets
+    let host = url
+        .host_str()
+        .ok_or_else(|| invalid("Webhook URL has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+
+    if is_allowed_host(host) {
+        return Ok(());
+    }
+
+    let addrs: Vec<IpAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|_| invalid("Webhook host could not be resolved"))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+
+    if !addrs.into_iter().all(is_public) {
+        return Err(invalid(
+            "Webhook URL must not target loopback, private or link-local addresses",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks whether an address is reachable from the internet, so requests to it cannot reach
+/// services on the server or in its network.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            // 100.64.0.0/10 is shared by carrier-grade NATs
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+fn is_allowed_host(host: &str) -> bool {
+    config::get()
+        .service_webhook_allowed_hosts
+        .split(',')
+        .map(str::trim)
+        .any(|allowed| !allowed.is_empty() && allowed.eq_ignore_ascii_case(host))
+}
+
+pub fn build_secret() -> String {
+    nanoid::nanoid!(SECRET_LENGTH)
+}
+
+pub fn build_id() -> String {
+    nanoid::nanoid!(ID_LENGTH)
+}
+
+async fn send(webhook: &Webhook, delivery: &Delivery) -> Result<(), String> {
+    // Addresses in the URL itself are not resolved, so they are checked here
+    check_url(&webhook.url)
+        .await
+        .map_err(|err| format!("Invalid target: {err}"))?;
+
+    let timestamp = utils::get_timestamp().millis;
+
+    let response = client()
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header("X-Elysium-Event", &delivery.event)
+        .header("X-Elysium-Delivery", &delivery.delivery_id)
+        .header("X-Elysium-Timestamp", timestamp.to_string())
+        .header(
+            "X-Elysium-Signature",
+            format!(
+                "sha256={}",
+                sign(&webhook.secret, timestamp, &delivery.payload)
+            ),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|err| format!("Request failed: {err}"))?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("Endpoint responded with {}", response.status()))
+    }
+}
+
+fn build_payload(event: &ChannelEvent) -> Option<(&'static str, String)> {
+    let (name, data) = match event {
+        ChannelEvent::MessageCreated(message) => (EVENT_MESSAGE_CREATED, message_payload(message)),
+        ChannelEvent::MessageUpdated(message) => (EVENT_MESSAGE_UPDATED, message_payload(message)),
+        ChannelEvent::MessageDeleted {
+            channel_id,
+            message_id,
+        } => (
+            EVENT_MESSAGE_DELETED,
+            json!({ "channel_id": channel_id, "message_id": message_id }),
+        ),
+        ChannelEvent::MemberChanged {
+            channel_id,
+            user_id,
+            permission,
+        } => (
+            EVENT_MEMBER_CHANGED,
+            json!({
+                "channel_id": channel_id,
+                "user_id": user_id,
+                "permission": permission.as_str_name(),
+            }),
+        ),
+        _ => return None,
+    };
+
+    Some((name, json!({ "event": name, "data": data }).to_string()))
+}
+
+fn message_payload(message: &Message) -> serde_json::Value {
+    json!({
+        "message_id": message.message_id,
+        "channel_id": message.channel_id,
+        "user_id": message.user_id,
+        "text": message.content.text,
+        "attachments": message
+            .content
+            .attachments
+            .iter()
+            .map(|id| json!({ "namespace": id.namespace, "key": id.key }))
+            .collect::<Vec<_>>(),
+        "created_at": message.content.created_at.millis,
+    })
+}
+
+fn client<'a>() -> &'a reqwest::Client {
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(config::get().service_webhook_timeout))
+            .dns_resolver(Arc::new(PublicResolver))
+            // A redirect could lead anywhere, so it counts as a failed delivery
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build webhook HTTP client")
+    })
+}
+
+/// Resolves webhook hosts to their public addresses only, see [`is_public`].
+///
+/// Checking on every connection keeps a host from resolving to an internal address after its
+/// URL was checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let allowed = is_allowed_host(&host);
+
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| allowed || is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("Host '{host}' has no public address").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// An outgoing webhook receiving events of a channel.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Webhook {
+    pub webhook_id: String,
+    pub channel_id: String,
+    pub url: String,
+    /// Secret used to sign deliveries.
+    pub secret: String,
+    /// Names of the events this webhook receives.
+    pub events: Vec<String>,
+    /// The user who created the webhook.
+    pub user_id: String,
+    pub created_at: Timestamp,
+}
+
+/// A queued or finished delivery of an event to a webhook.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct Delivery {
+    pub delivery_id: String,
+    pub webhook_id: String,
+    pub event: String,
+    /// JSON body sent to the webhook.
+    pub payload: String,
+    pub status: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub created_at: Timestamp,
+    pub next_attempt: Timestamp,
+}
+
+/// A token that lets external tools post messages into a channel.
+#[derive(Clone, Debug, SurrealValue)]
+pub struct IncomingWebhook {
+    pub webhook_id: String,
+    pub channel_id: String,
+    /// Display name of the bot user posting the messages.
+    pub name: String,
+    /// SHA-256 hash of the token secret.
+    pub secret_hash: String,
+    /// The user who created the webhook.
+    pub user_id: String,
+    pub created_at: Timestamp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    const PAYLOAD: &str = r#"{"event":"message.created"}"#;
+
+    /// Accepts one request on a local port, answers it with `response` and returns the URL and
+    /// the raw request.
+    async fn serve_once(response: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+
+            while !is_complete(&request) {
+                let read = stream.read(&mut buf).await.unwrap();
+
+                if read == 0 {
+                    break;
+                }
+
+                request.extend_from_slice(&buf[..read]);
+            }
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, server)
+    }
+
+    fn is_complete(request: &[u8]) -> bool {
+        let request = String::from_utf8_lossy(request);
+
+        let Some((head, body)) = request.split_once("\r\n\r\n") else {
+            return false;
+        };
+
+        let length = header(head, "content-length")
+            .and_then(|length| length.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        body.len() >= length
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        request.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    fn build_webhook(url: String) -> Webhook {
+        Webhook {
+            webhook_id: "hook".to_string(),
+            channel_id: "channel".to_string(),
+            url,
+            secret: "secret".to_string(),
+            events: vec![EVENT_MESSAGE_CREATED.to_string()],
+            user_id: "user".to_string(),
+            created_at: Timestamp { millis: 0 },
+        }
+    }
+
+    fn build_delivery() -> Delivery {
+        Delivery {
+            delivery_id: "delivery".to_string(),
+            webhook_id: "hook".to_string(),
+            event: EVENT_MESSAGE_CREATED.to_string(),
+            payload: PAYLOAD.to_string(),
+            status: DELIVERY_PENDING.to_string(),
+            attempts: 0,
+            last_error: String::new(),
+            created_at: Timestamp { millis: 0 },
+            next_attempt: Timestamp { millis: 0 },
+        }
+    }
+
+    #[test]
+    fn sign_matches_reference() {
+        assert_eq!(
+            sign("secret", 1700000000000, PAYLOAD),
+            "85ee6fa69a1338058caff5a725bc860136b89c8a8948babf6c886ed80737acb0"
+        );
+    }
+
+    #[test]
+    fn sign_covers_timestamp_and_secret() {
+        let signature = sign("secret", 1700000000000, PAYLOAD);
+
+        assert_ne!(sign("secret", 1700000000001, PAYLOAD), signature);
+        assert_ne!(sign("other", 1700000000000, PAYLOAD), signature);
+    }
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is not public");
+        }
+
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[tokio::test]
+    async fn check_url_rejects_internal_targets() {
+        config::init_test();
+
+        for url in [
+            "http://10.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "https://[fe80::1]/hook",
+            "ftp://example.com/hook",
+            "not a url",
+        ] {
+            assert!(check_url(url).await.is_err(), "{url} is rejected");
+        }
+
+        // Loopback is allowed by the default debug configuration
+        assert!(check_url("http://127.0.0.1:8080/hook").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_posts_signed_payload() {
+        config::init_test();
+
+        let (url, server) =
+            serve_once("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+
+        send(&build_webhook(url), &build_delivery()).await.unwrap();
+
+        let request = server.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let timestamp: u64 = header(head, "x-elysium-timestamp")
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        assert!(head.starts_with("POST /hook HTTP/1.1"));
+        assert_eq!(header(head, "x-elysium-event"), Some(EVENT_MESSAGE_CREATED));
+        assert_eq!(header(head, "x-elysium-delivery"), Some("delivery"));
+        assert_eq!(
+            header(head, "x-elysium-signature"),
+            Some(format!("sha256={}", sign("secret", timestamp, PAYLOAD)).as_str())
+        );
+        assert_eq!(body, PAYLOAD);
+    }
+
+    #[tokio::test]
+    async fn send_fails_on_error_status() {
+        config::init_test();
+
+        let (url, server) = serve_once(
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+
+        let err = send(&build_webhook(url), &build_delivery())
+            .await
+            .unwrap_err();
+
+        server.await.unwrap();
+        assert!(err.contains("500"), "{err}");
+    }
+}